@@ -0,0 +1,242 @@
+//! Logs and transaction receipts of emitted blocks.
+//!
+//! Only logs matching the configured contract addresses and topics are
+//! included, together with the receipts of the transactions that emitted them.
+
+use std::sync::Mutex;
+
+use anyhow::{Context as _, Result as AnyResult};
+use futures::future::try_join_all;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, error};
+use types::{proto::BlockData as BlockDataProto, IntoProto, MissingField, TryFromProto};
+use url::Url;
+use web3::{
+    api::Eth,
+    types::{BlockHeader, FilterBuilder, Log, TransactionReceipt},
+};
+
+use super::{connect, retry_delay, statistics::BLOCK_DATA_LATENCY, Error, Options, Transport};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockData {
+    pub header:   BlockHeader,
+    pub logs:     Vec<Log>,
+    pub receipts: Vec<TransactionReceipt>,
+}
+
+impl IntoProto for BlockData {
+    type Proto = BlockDataProto;
+
+    fn into_proto(self) -> Self::Proto {
+        Self::Proto {
+            header:   Some(self.header.into_proto()),
+            logs:     self.logs.into_iter().map(Log::into_proto).collect(),
+            receipts: self
+                .receipts
+                .into_iter()
+                .map(TransactionReceipt::into_proto)
+                .collect(),
+        }
+    }
+}
+
+impl TryFromProto for BlockData {
+    type Proto = BlockDataProto;
+
+    fn try_from_proto(p: Self::Proto) -> Result<Self, MissingField> {
+        Ok(Self {
+            header:   BlockHeader::try_from_proto(p.header.ok_or(MissingField("header"))?)?,
+            logs:     p
+                .logs
+                .into_iter()
+                .map(Log::try_from_proto)
+                .collect::<Result<_, _>>()?,
+            receipts: p
+                .receipts
+                .into_iter()
+                .map(TransactionReceipt::try_from_proto)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Fetches [`BlockData`] for emitted headers, moving on to the next provider
+/// on failure.
+pub struct Fetcher {
+    urls:        Vec<Url>,
+    options:     Options,
+    connections: Mutex<Vec<Option<Eth<Transport>>>>,
+}
+
+impl Fetcher {
+    pub fn new(urls: Vec<Url>, options: Options) -> Self {
+        let connections = Mutex::new(vec![None; urls.len()]);
+        Self {
+            urls,
+            options,
+            connections,
+        }
+    }
+
+    pub async fn fetch(&self, header: BlockHeader) -> AnyResult<BlockData> {
+        for (retries, (provider, url)) in self.urls.iter().enumerate().cycle().enumerate() {
+            let error = match self.fetch_from(provider, url, &header).await {
+                Ok(data) => return Ok(data),
+                Err(e) => e,
+            };
+            error!(?error, %url, "Error fetching block data");
+            self.connections.lock().unwrap()[provider] = None;
+
+            // Abort if maximum number of retries was exceeded
+            if retries > self.options.max_tries {
+                return Err(error).context("Maximum retries exceeded");
+            }
+
+            // Jitter delay.
+            sleep(retry_delay(&self.options, retries)).await;
+        }
+        unreachable!("Fetcher requires at least one provider")
+    }
+
+    async fn fetch_from(
+        &self,
+        provider: usize,
+        url: &Url,
+        header: &BlockHeader,
+    ) -> Result<BlockData, Error> {
+        let connection = self.connections.lock().unwrap()[provider].clone();
+        let eth = if let Some(eth) = connection {
+            eth
+        } else {
+            let (eth, sub) = connect(url).await?;
+            drop(sub);
+            self.connections.lock().unwrap()[provider] = Some(eth.clone());
+            eth
+        };
+        fetch_block_data(&self.options, &eth, header).await
+    }
+}
+
+async fn fetch_block_data(
+    options: &Options,
+    eth: &Eth<Transport>,
+    header: &BlockHeader,
+) -> Result<BlockData, Error> {
+    let _timer = BLOCK_DATA_LATENCY.start_timer(); // Observe on drop
+    let hash = header.hash.ok_or(Error::HashMissing)?;
+
+    // Fetch matching logs. Filtering by hash guarantees they belong to this
+    // block, even if it has since been re-orged.
+    let mut filter = FilterBuilder::default().block_hash(hash);
+    if !options.log_addresses.is_empty() {
+        filter = filter.address(options.log_addresses.clone());
+    }
+    if !options.log_topics.is_empty() {
+        filter = filter.topics(Some(options.log_topics.clone()), None, None, None);
+    }
+    let logs = timeout(options.fetch_timeout, eth.logs(filter.build())).await??;
+
+    // Fetch receipts of the transactions that emitted them. Logs are ordered
+    // by transaction, so consecutive duplicates are all duplicates.
+    let mut transactions = logs
+        .iter()
+        .filter_map(|log| log.transaction_hash)
+        .collect::<Vec<_>>();
+    transactions.dedup();
+    let receipts = try_join_all(transactions.into_iter().map(|transaction| {
+        async move {
+            let receipt = timeout(options.fetch_timeout, eth.transaction_receipt(transaction))
+                .await??
+                .ok_or(Error::NotFound)?;
+            if receipt.block_hash != Some(hash) {
+                return Err(Error::InsaneBlockHash);
+            }
+            Ok(receipt)
+        }
+    }))
+    .await?;
+
+    debug!(
+        ?hash,
+        logs = logs.len(),
+        receipts = receipts.len(),
+        "Fetched block data"
+    );
+    Ok(BlockData {
+        header: header.clone(),
+        logs,
+        receipts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{Address, H2048, H256};
+
+    use super::*;
+
+    #[test]
+    fn test_block_data_roundtrip() {
+        let header: BlockHeader = serde_json::from_value(serde_json::json!({
+            "hash": H256::repeat_byte(1),
+            "parentHash": H256::repeat_byte(2),
+            "sha3Uncles": H256::repeat_byte(3),
+            "miner": Address::repeat_byte(4),
+            "stateRoot": H256::repeat_byte(5),
+            "transactionsRoot": H256::repeat_byte(6),
+            "receiptsRoot": H256::repeat_byte(7),
+            "number": "0x8",
+            "gasUsed": "0x9",
+            "gasLimit": "0xa",
+            "extraData": "0x0b",
+            "logsBloom": H2048::repeat_byte(12),
+            "timestamp": "0xd",
+            "difficulty": "0xe",
+        }))
+        .unwrap();
+        let log = Log {
+            address:               Address::repeat_byte(15),
+            topics:                vec![H256::repeat_byte(16)],
+            data:                  vec![17].into(),
+            block_hash:            header.hash,
+            block_number:          header.number,
+            transaction_hash:      Some(H256::repeat_byte(18)),
+            transaction_index:     Some(0.into()),
+            log_index:             Some(0.into()),
+            transaction_log_index: None,
+            log_type:              None,
+            removed:               Some(false),
+        };
+        let receipt = TransactionReceipt {
+            transaction_hash:    H256::repeat_byte(18),
+            transaction_index:   0.into(),
+            block_hash:          header.hash,
+            block_number:        header.number,
+            from:                Address::repeat_byte(19),
+            to:                  Some(log.address),
+            cumulative_gas_used: 20.into(),
+            gas_used:            Some(20.into()),
+            contract_address:    None,
+            logs:                vec![log.clone()],
+            status:              Some(1.into()),
+            root:                None,
+            logs_bloom:          H2048::repeat_byte(21),
+            transaction_type:    None,
+        };
+        let data = BlockData {
+            header,
+            logs: vec![log],
+            receipts: vec![receipt],
+        };
+        assert_eq!(
+            BlockData::try_from_proto(data.clone().into_proto()),
+            Ok(data)
+        );
+
+        assert_eq!(
+            BlockData::try_from_proto(BlockDataProto::default()),
+            Err(MissingField("header"))
+        );
+    }
+}
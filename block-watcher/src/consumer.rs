@@ -1,9 +1,20 @@
-use anyhow::Result as AnyResult;
-use futures::{Stream, StreamExt};
-use types::{proto::BlockHeader as BlockHeaderProto, FromProto, Kafka, KafkaConsumer, Options};
+use anyhow::{Error as AnyError, Result as AnyResult};
+use futures::{future::ready, Stream, StreamExt};
+use tracing::error;
+use types::{
+    proto::{
+        zeroex::reorgable::Event as EventProto, BlockData as BlockDataProto,
+        BlockHeader as BlockHeaderProto,
+    },
+    FromProto, Kafka, KafkaConsumer, KafkaOffset, Options, TryFromProto,
+};
 use web3::types::BlockHeader;
 
-pub struct Consumer(KafkaConsumer<BlockHeaderProto>);
+use super::{BlockData, Reorgable};
+
+/// Consumer for the block headers and re-orgs published with
+/// `BLOCK_WATCHER_TOPIC`.
+pub struct Consumer(KafkaConsumer<EventProto>);
 
 impl Consumer {
     pub async fn new(input_topic: String, options: Options) -> AnyResult<Self> {
@@ -11,7 +22,90 @@ impl Consumer {
         Ok(Self(kafka.new_consumer(&input_topic).await?))
     }
 
+    /// Stream of events, skipping incomplete messages.
+    pub fn stream(&self) -> impl Stream<Item = Reorgable<BlockHeader>> + '_ {
+        self.0
+            .stream()
+            .filter_map(|x| ready(log_error(x).and_then(event)))
+    }
+
+    /// Stream of events with their offsets for [`Self::commit`].
+    pub fn stream_with_offsets(
+        &self,
+    ) -> impl Stream<Item = (Reorgable<BlockHeader>, KafkaOffset)> + '_ {
+        self.0.stream_with_offsets().filter_map(|x| {
+            ready(log_error(x).and_then(|(proto, offset)| Some((event(proto)?, offset))))
+        })
+    }
+
+    /// Commit events up to and including `offset` as processed.
+    pub fn commit(&self, offset: KafkaOffset) -> AnyResult<()> {
+        self.0.commit(offset)
+    }
+}
+
+/// Consumer for the headers published with `SAFE_TOPIC` and
+/// `FINALIZED_TOPIC`, which are never re-orged.
+pub struct FinalityConsumer(KafkaConsumer<BlockHeaderProto>);
+
+impl FinalityConsumer {
+    pub async fn new(input_topic: String, options: Options) -> AnyResult<Self> {
+        let kafka = Kafka::new(options).await?;
+        Ok(Self(kafka.new_consumer(&input_topic).await?))
+    }
+
     pub fn stream(&self) -> impl Stream<Item = BlockHeader> + '_ {
-        self.0.stream().map(|x| BlockHeader::from_proto(x.unwrap()))
+        self.0
+            .stream()
+            .filter_map(|x| ready(log_error(x)))
+            .map(BlockHeader::from_proto)
+    }
+
+    /// Stream of headers with their offsets for [`Self::commit`].
+    pub fn stream_with_offsets(&self) -> impl Stream<Item = (BlockHeader, KafkaOffset)> + '_ {
+        self.0
+            .stream_with_offsets()
+            .filter_map(|x| ready(log_error(x)))
+            .map(|(header, offset)| (BlockHeader::from_proto(header), offset))
     }
+
+    /// Commit headers up to and including `offset` as processed.
+    pub fn commit(&self, offset: KafkaOffset) -> AnyResult<()> {
+        self.0.commit(offset)
+    }
+}
+
+/// Consumer for the logs and receipts published with `BLOCK_DATA_TOPIC`.
+pub struct BlockDataConsumer(KafkaConsumer<BlockDataProto>);
+
+impl BlockDataConsumer {
+    pub async fn new(input_topic: String, options: Options) -> AnyResult<Self> {
+        let kafka = Kafka::new(options).await?;
+        Ok(Self(kafka.new_consumer(&input_topic).await?))
+    }
+
+    /// Stream of block data, skipping incomplete messages.
+    pub fn stream(&self) -> impl Stream<Item = BlockData> + '_ {
+        self.0.stream().filter_map(|x| {
+            ready(log_error(x).and_then(|data| {
+                BlockData::try_from_proto(data)
+                    .map_err(|error| error!(%error, "Skipping incomplete block data"))
+                    .ok()
+            }))
+        })
+    }
+}
+
+fn event(proto: EventProto) -> Option<Reorgable<BlockHeader>> {
+    Reorgable::try_from_proto(proto)
+        .map_err(|error| error!(%error, "Skipping incomplete block event"))
+        .ok()
+}
+
+/// Log and skip Kafka errors. Undecodable messages are already sent to the
+/// dead-letter topic by the consumer.
+fn log_error<T>(result: Result<T, AnyError>) -> Option<T> {
+    result
+        .map_err(|error| error!("Error receiving Kafka message: {:#}", error))
+        .ok()
 }
@@ -0,0 +1,272 @@
+//! Track the `safe` and `finalized` blocks.
+//!
+//! Post-merge chains expose these as block tags. For other chains they can be
+//! approximated by lagging the latest block by a number of confirmations.
+
+use core::str::FromStr;
+
+use anyhow::{anyhow, Context as _, Error as AnyError, Result as AnyResult};
+use futures::FutureExt;
+use tokio::{
+    spawn,
+    sync::mpsc::{channel, Receiver, Sender},
+    time::{sleep, timeout},
+};
+use tracing::{debug, error, info};
+use url::Url;
+use web3::{
+    api::{Eth, Namespace},
+    types::{Block, BlockHeader, BlockNumber, H256},
+    Transport as _,
+};
+
+use super::{
+    block_to_header, connect, fetch_header, retry_delay, statistics::BLOCK_HEADER_LATENCY, Error,
+    Options, Transport,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Finality {
+    Safe,
+    Finalized,
+}
+
+pub type FinalityEvent = (Finality, BlockHeader);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FinalityMode {
+    Disabled,
+    Tags,
+    Confirmations,
+}
+
+impl FromStr for FinalityMode {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::Disabled),
+            "tags" => Ok(Self::Tags),
+            "confirmations" => Ok(Self::Confirmations),
+            _ => Err(anyhow!("Invalid finality mode: {}", s)),
+        }
+    }
+}
+
+/// Start finality tracking task
+pub fn start(urls: Vec<Url>, options: Options) -> AnyResult<Receiver<FinalityEvent>> {
+    if options.finality == FinalityMode::Disabled {
+        return Err(anyhow!("Finality tracking is disabled."));
+    }
+    let (sender, receiver) = channel(options.queue_capacity);
+
+    spawn(run(urls, options, sender).map(|result| {
+        if let Err(error) = result {
+            error!(?error, "Error in task");
+            std::process::abort();
+        }
+    }));
+
+    Ok(receiver)
+}
+
+/// Poll providers in turn, moving on to the next provider on failure
+async fn run(urls: Vec<Url>, options: Options, sender: Sender<FinalityEvent>) -> AnyResult<()> {
+    let mut last = [0; 2];
+    let mut retries = 0;
+    for url in urls.iter().cycle() {
+        let mut progress = false;
+        let error = match poll(url, &options, &sender, &mut last, &mut progress).await {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+        if let Error::ReceiverClosed = error {
+            return Err(error.into());
+        }
+        error!(?error, %url, "Finality polling failed");
+
+        // Reset try counter if progress was made
+        if progress {
+            retries = 0;
+        }
+
+        // Abort if maximum number of retries was exceeded
+        if retries > options.max_tries {
+            return Err(error).context("Maximum retries exceeded");
+        }
+
+        // Jitter delay.
+        sleep(retry_delay(&options, retries)).await;
+        retries += 1;
+    }
+    Ok(())
+}
+
+/// Poll a single provider for new safe and finalized blocks
+async fn poll(
+    url: &Url,
+    options: &Options,
+    sender: &Sender<FinalityEvent>,
+    last: &mut [u64; 2],
+    progress: &mut bool,
+) -> Result<(), Error> {
+    let (eth, sub) = connect(url).await?;
+    drop(sub);
+    loop {
+        for (finality, header) in fetch_finality(options, &eth).await? {
+            let number = header.number.ok_or(Error::NumberMissing)?.as_u64();
+            let last = &mut last[finality as usize];
+            if number > *last {
+                info!(?finality, number, "New block finality");
+                *last = number;
+                *progress = true;
+                sender
+                    .send((finality, header))
+                    .await
+                    .map_err(|_| Error::ReceiverClosed)?;
+            }
+        }
+        sleep(options.poll_delay).await;
+    }
+}
+
+async fn fetch_finality(
+    options: &Options,
+    eth: &Eth<Transport>,
+) -> Result<[FinalityEvent; 2], Error> {
+    let (safe, finalized) = match options.finality {
+        FinalityMode::Disabled => return Err(Error::NotFound),
+        FinalityMode::Tags => {
+            (
+                fetch_tagged(options, eth, "safe").await?,
+                fetch_tagged(options, eth, "finalized").await?,
+            )
+        }
+        FinalityMode::Confirmations => {
+            let latest = fetch_header(options, eth, BlockNumber::Latest).await?;
+            let number = latest.number.ok_or(Error::NumberMissing)?.as_u64();
+            let safe = number.saturating_sub(options.safe_confirmations);
+            let finalized = number.saturating_sub(options.finalized_confirmations);
+            (
+                fetch_header(options, eth, BlockNumber::Number(safe.into())).await?,
+                fetch_header(options, eth, BlockNumber::Number(finalized.into())).await?,
+            )
+        }
+    };
+    Ok([(Finality::Safe, safe), (Finality::Finalized, finalized)])
+}
+
+/// Fetch a header by block tag. The `safe` and `finalized` tags are not
+/// supported by [`BlockNumber`], so the request is made directly.
+async fn fetch_tagged(
+    options: &Options,
+    eth: &Eth<Transport>,
+    tag: &str,
+) -> Result<BlockHeader, Error> {
+    let _timer = BLOCK_HEADER_LATENCY.start_timer(); // Observe on drop
+    let request = eth
+        .transport()
+        .execute("eth_getBlockByNumber", vec![tag.into(), false.into()]);
+    let value = timeout(options.fetch_timeout, request).await??;
+    let block: Option<Block<H256>> =
+        serde_json::from_value(value).map_err(|error| web3::Error::Decoder(error.to_string()))?;
+    let header = block_to_header(block.ok_or(Error::NotFound)?);
+    let number = header.number.ok_or(Error::NumberMissing)?;
+    let hash = header.hash.ok_or(Error::HashMissing)?;
+    debug!(tag, ?number, ?hash, "Fetched header");
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+    use crate::tests::{mock_provider, MockChain};
+
+    fn options(finality: FinalityMode) -> Options {
+        Options {
+            finality,
+            safe_confirmations: 2,
+            finalized_confirmations: 5,
+            poll_delay: Duration::from_millis(10),
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn test_finality_mode() {
+        assert_eq!(
+            "none".parse::<FinalityMode>().unwrap(),
+            FinalityMode::Disabled
+        );
+        assert_eq!("tags".parse::<FinalityMode>().unwrap(), FinalityMode::Tags);
+        assert_eq!(
+            "confirmations".parse::<FinalityMode>().unwrap(),
+            FinalityMode::Confirmations
+        );
+        assert!("finalized".parse::<FinalityMode>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_confirmations() {
+        let options = options(FinalityMode::Confirmations);
+        let chain = MockChain::new(20);
+        let (eth, _) = connect(&mock_provider(&chain).0).await.unwrap();
+        let expected = {
+            let chain = chain.lock().unwrap();
+            [
+                (Finality::Safe, chain.header(17)),
+                (Finality::Finalized, chain.header(14)),
+            ]
+        };
+        assert_eq!(fetch_finality(&options, &eth).await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_tags() {
+        let options = options(FinalityMode::Tags);
+        let chain = MockChain::new(20);
+        let (eth, _) = connect(&mock_provider(&chain).0).await.unwrap();
+
+        // Pre-merge providers do not know the tags
+        assert!(matches!(
+            fetch_finality(&options, &eth).await,
+            Err(Error::NotFound)
+        ));
+
+        let expected = {
+            let mut chain = chain.lock().unwrap();
+            chain.safe = Some(12);
+            chain.finalized = Some(3);
+            [
+                (Finality::Safe, chain.header(12)),
+                (Finality::Finalized, chain.header(3)),
+            ]
+        };
+        assert_eq!(fetch_finality(&options, &eth).await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let options = options(FinalityMode::Confirmations);
+        let chain = MockChain::new(20);
+        let (url, _) = mock_provider(&chain);
+        let (sender, mut receiver) = channel(options.queue_capacity);
+        spawn(run(vec![url], options, sender));
+        let header = |number| chain.lock().unwrap().header(number);
+        assert_eq!(receiver.recv().await, Some((Finality::Safe, header(17))));
+        assert_eq!(
+            receiver.recv().await,
+            Some((Finality::Finalized, header(14)))
+        );
+
+        // Blocks are only sent once, when they become safe or finalized
+        chain.lock().unwrap().extend(21);
+        assert_eq!(receiver.recv().await, Some((Finality::Safe, header(18))));
+        assert_eq!(
+            receiver.recv().await,
+            Some((Finality::Finalized, header(15)))
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Ring of recently emitted block headers.
+//!
+//! The history is used to find the common ancestor on re-orgs deeper than the
+//! in-memory queue. It is optionally persisted to a file so it survives
+//! restarts.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{Error as IoError, ErrorKind},
+    path::PathBuf,
+};
+
+use anyhow::{Context as _, Result as AnyResult};
+use tracing::{debug, info};
+use web3::types::BlockHeader;
+
+#[derive(Debug)]
+pub struct History {
+    capacity: usize,
+    path:     Option<PathBuf>,
+    headers:  VecDeque<BlockHeader>,
+}
+
+impl History {
+    /// Create a new history, loading previously persisted headers from `path`
+    /// if it exists.
+    pub fn load(capacity: usize, path: Option<PathBuf>) -> AnyResult<Self> {
+        let mut headers = VecDeque::with_capacity(capacity + 1);
+        if let Some(path) = &path {
+            match fs::read(path) {
+                Ok(data) => {
+                    headers = serde_json::from_slice(&data).with_context(|| {
+                        format!("Error decoding header history {}", path.display())
+                    })?;
+                    info!(
+                        "Loaded {} headers from history {}",
+                        headers.len(),
+                        path.display()
+                    );
+                }
+                Err(error) if error.kind() == ErrorKind::NotFound => {}
+                Err(error) => {
+                    return Err(error).with_context(|| {
+                        format!("Error reading header history {}", path.display())
+                    });
+                }
+            }
+        }
+        let mut history = Self {
+            capacity,
+            path,
+            headers,
+        };
+        history.truncate();
+        Ok(history)
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&BlockHeader> {
+        self.headers.get(index)
+    }
+
+    /// The oldest known header
+    pub fn first(&self) -> Option<&BlockHeader> {
+        self.headers.front()
+    }
+
+    /// The last emitted header
+    pub fn last(&self) -> Option<&BlockHeader> {
+        self.headers.back()
+    }
+
+    /// Append a newly emitted header, dropping the oldest if over capacity.
+    pub fn push(&mut self, header: BlockHeader) {
+        self.headers.push_back(header);
+        self.truncate();
+    }
+
+    /// Remove all headers with block number `block_height` or higher.
+    pub fn rewind(&mut self, block_height: u64) {
+        while self
+            .last()
+            .and_then(|header| header.number)
+            .map_or(false, |number| number.as_u64() >= block_height)
+        {
+            self.headers.pop_back();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.headers.clear();
+    }
+
+    /// Write the history to disk, if a path is configured.
+    pub fn save(&self) -> Result<(), IoError> {
+        if let Some(path) = &self.path {
+            // Write to a temporary file first so a crash can not leave a
+            // partially written history.
+            let temp = path.with_extension("tmp");
+            fs::write(&temp, serde_json::to_vec(&self.headers)?)?;
+            fs::rename(&temp, path)?;
+            debug!("Saved {} headers to {}", self.len(), path.display());
+        }
+        Ok(())
+    }
+
+    fn truncate(&mut self) {
+        while self.headers.len() > self.capacity {
+            self.headers.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{Block, H256};
+
+    use super::*;
+    use crate::block_to_header;
+
+    fn header(number: u64) -> BlockHeader {
+        block_to_header(Block {
+            hash: Some(H256::from_low_u64_be(number)),
+            parent_hash: H256::from_low_u64_be(number.saturating_sub(1)),
+            number: Some(number.into()),
+            ..Block::default()
+        })
+    }
+
+    #[test]
+    fn test_ring() {
+        let mut history = History::load(3, None).unwrap();
+        for number in 1..=5 {
+            history.push(header(number));
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.first(), Some(&header(3)));
+        assert_eq!(history.get(1), Some(&header(4)));
+        assert_eq!(history.get(3), None);
+
+        history.rewind(4);
+        assert_eq!(history.last(), Some(&header(3)));
+    }
+}
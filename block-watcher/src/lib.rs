@@ -1,47 +1,156 @@
+pub mod block_data;
 pub mod consumer;
+mod finality;
+mod history;
 pub mod producer;
 mod statistics;
 
-use core::{f64, time::Duration};
+use core::{convert::TryFrom, f64, time::Duration};
+use std::path::PathBuf;
 
-use anyhow::{anyhow, Context as _, Result as AnyResult};
-use chrono::{TimeZone, Utc};
+use anyhow::{anyhow, Context as _, Error as AnyError, Result as AnyResult};
+use chrono::{DateTime, TimeZone, Utc};
 use futures::{FutureExt, StreamExt};
+use history::History;
+use humantime::parse_duration;
+use rand::{thread_rng, Rng as _};
 use statistics::{
-    BLOCKS_ADDED, BLOCKS_RECEIVED, BLOCKS_REWOUND, BLOCK_HEADER_AGE, BLOCK_HEADER_LATENCY,
-    BLOCK_TIME, CONNECTION_ATTEMPTS,
+    BLOCKS_ADDED, BLOCKS_BACKFILLED, BLOCKS_RECEIVED, BLOCKS_REWOUND, BLOCK_HEADER_AGE,
+    BLOCK_HEADER_LATENCY, BLOCK_TIME, CONNECTION_ATTEMPTS, DEEP_REORGS, PROVIDERS_FAILED,
+    STALE_HEADERS,
 };
+use structopt::StructOpt;
 use thiserror::Error;
 use tokio::{
     select, spawn,
-    sync::broadcast::{channel, Receiver, Sender},
+    sync::mpsc::{self, channel, Receiver, Sender},
     time::{sleep, timeout},
 };
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+use types::{
+    proto::zeroex::reorgable::{
+        event::Event as EventKindProto, Event as EventProto, Reorg as ReorgProto,
+    },
+    IntoProto, MissingField, TryFromProto,
+};
 use url::Url;
 use web3::{
     api::{Eth, EthSubscribe, Namespace, SubscriptionStream},
-    transports::WebSocket,
-    types::{Block, BlockHeader, BlockId, BlockNumber, H256},
+    transports::{Either, Http, WebSocket},
+    types::{Address, Block, BlockHeader, BlockId, BlockNumber, H256, U256},
 };
 
-/// Max number of blocks in the event queue
-const QUEUE_CAPACITY: usize = 20;
-
-/// Time to wait on stream before trying to poll
-const POLL_DELAY: Duration = Duration::from_secs(5);
-
-/// Timeout on the [`Eth::block`] request
-const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
-
-/// Maximum amount of success
-const MAX_TRIES: usize = 10;
+pub use self::{
+    block_data::{BlockData, Fetcher as BlockDataFetcher},
+    finality::{start as start_finality, Finality, FinalityEvent, FinalityMode},
+};
 
-/// Time to wait between connection retries
-const RETRY_DELAY: Duration = Duration::from_secs(1);
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct Options {
+    /// Max number of blocks in the event queue
+    #[structopt(long, env = "QUEUE_CAPACITY", default_value = "20")]
+    pub queue_capacity: usize,
+
+    /// Time to wait on stream before trying to poll
+    #[structopt(
+        long,
+        env = "POLL_DELAY",
+        default_value = "5s",
+        parse(try_from_str = parse_duration)
+    )]
+    pub poll_delay: Duration,
+
+    /// Timeout on block requests
+    #[structopt(
+        long,
+        env = "FETCH_TIMEOUT",
+        default_value = "5s",
+        parse(try_from_str = parse_duration)
+    )]
+    pub fetch_timeout: Duration,
+
+    /// Maximum number of connection retries without progress
+    #[structopt(long, env = "MAX_TRIES", default_value = "10")]
+    pub max_tries: usize,
+
+    /// Initial time to wait between connection retries, doubled on every retry
+    #[structopt(
+        long,
+        env = "RETRY_DELAY",
+        default_value = "1s",
+        parse(try_from_str = parse_duration)
+    )]
+    pub retry_delay: Duration,
+
+    /// Maximum time to wait between connection retries
+    #[structopt(
+        long,
+        env = "MAX_RETRY_DELAY",
+        default_value = "60s",
+        parse(try_from_str = parse_duration)
+    )]
+    pub max_retry_delay: Duration,
+
+    /// Maximum acceptable re-org size. Deeper re-orgs are recovered from using
+    /// the header history.
+    #[structopt(long, env = "MAX_REORG", default_value = "10")]
+    pub max_reorg: usize,
+
+    /// Number of recent headers to keep for finding the common ancestor in
+    /// deep re-orgs
+    #[structopt(long, env = "HISTORY_SIZE", default_value = "256")]
+    pub history_size: usize,
+
+    /// File to persist the header history in. On restart, blocks produced
+    /// since the last emitted header are backfilled.
+    #[structopt(long, env = "HISTORY_FILE", parse(from_os_str))]
+    pub history_file: Option<PathBuf>,
+
+    /// Maximum number of missing blocks to backfill. Older missing blocks are
+    /// skipped.
+    #[structopt(long, env = "MAX_BACKFILL", default_value = "1000")]
+    pub max_backfill: usize,
+
+    /// Track safe and finalized blocks, one of 'none', 'tags' (post-merge
+    /// block tags) or 'confirmations'
+    #[structopt(long, env = "FINALITY", default_value = "none")]
+    pub finality: FinalityMode,
+
+    /// Number of confirmations for a block to be safe in 'confirmations' mode
+    #[structopt(long, env = "SAFE_CONFIRMATIONS", default_value = "12")]
+    pub safe_confirmations: u64,
+
+    /// Number of confirmations for a block to be finalized in 'confirmations'
+    /// mode
+    #[structopt(long, env = "FINALIZED_CONFIRMATIONS", default_value = "64")]
+    pub finalized_confirmations: u64,
+
+    /// Comma separated list of contract addresses to publish logs and
+    /// receipts for. Logs of all contracts are published if empty.
+    #[structopt(long, env = "LOG_ADDRESSES", use_delimiter = true)]
+    pub log_addresses: Vec<Address>,
+
+    /// Comma separated list of event signatures (first topic) to publish logs
+    /// and receipts for. All events are published if empty.
+    #[structopt(long, env = "LOG_TOPICS", use_delimiter = true)]
+    pub log_topics: Vec<H256>,
+
+    /// Maximum age of a provider's head before the provider is considered
+    /// lagging
+    #[structopt(
+        long,
+        env = "MAX_HEAD_AGE",
+        default_value = "60s",
+        parse(try_from_str = parse_duration)
+    )]
+    pub max_head_age: Duration,
+}
 
-/// Maximum acceptable re-org size
-const MAX_REORG: usize = 10;
+impl Default for Options {
+    fn default() -> Self {
+        Self::from_iter(&[""])
+    }
+}
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Reorgable<T> {
@@ -57,6 +166,54 @@ impl<T> From<T> for Reorgable<T> {
 
 type Event = Reorgable<BlockHeader>;
 
+impl IntoProto for Event {
+    type Proto = EventProto;
+
+    fn into_proto(self) -> Self::Proto {
+        match self {
+            Self::Event(header) => {
+                EventProto {
+                    block_height: header.number.unwrap_or_default().as_u64(),
+                    event:        Some(EventKindProto::BlockHeader(header.into_proto())),
+                }
+            }
+            Self::Reorg { block_height } => {
+                EventProto {
+                    block_height,
+                    event: Some(EventKindProto::Reorg(ReorgProto {})),
+                }
+            }
+        }
+    }
+}
+
+impl TryFromProto for Event {
+    type Proto = EventProto;
+
+    fn try_from_proto(p: Self::Proto) -> Result<Self, MissingField> {
+        match p.event.ok_or(MissingField("event"))? {
+            EventKindProto::BlockHeader(header) => {
+                BlockHeader::try_from_proto(header).map(Self::Event)
+            }
+            EventKindProto::Reorg(ReorgProto {}) => {
+                Ok(Self::Reorg {
+                    block_height: p.block_height,
+                })
+            }
+        }
+    }
+}
+
+/// Either a WebSocket or a HTTP provider.
+type Transport = Either<WebSocket, Http>;
+
+/// A new head received from one of the providers.
+struct Head {
+    provider: usize,
+    eth:      Eth<Transport>,
+    header:   BlockHeader,
+}
+
 #[derive(Error, Debug)]
 #[allow(clippy::enum_variant_names)]
 enum Error {
@@ -78,19 +235,32 @@ enum Error {
     InsaneParentHash,
     #[error("Sanity check failed: non-consecutive block numbers")]
     InsaneNumber,
+    #[error("Sanity check failed: data belongs to a different block")]
+    InsaneBlockHash,
+    #[error("Event receiver closed")]
+    ReceiverClosed,
 }
 
 /// Start blockwatcher task
-pub fn start(url: Url) -> AnyResult<Receiver<Event>> {
-    if !matches!(url.scheme(), "ws" | "wss") {
-        return Err(anyhow!(
-            "Unsupported ethereum transport {}. Use ws or wss.",
-            url.scheme()
-        ));
+///
+/// All `urls` are followed concurrently and the longest valid chain across
+/// the providers is emitted. Events are never dropped, if the receiver falls
+/// behind the watcher waits for it.
+pub fn start(urls: Vec<Url>, options: Options) -> AnyResult<Receiver<Event>> {
+    if urls.is_empty() {
+        return Err(anyhow!("At least one ethereum provider is required."));
     }
-    let (sender, receiver) = channel(QUEUE_CAPACITY);
+    for url in &urls {
+        if !matches!(url.scheme(), "ws" | "wss" | "http" | "https") {
+            return Err(anyhow!(
+                "Unsupported ethereum transport {}. Use ws, wss, http or https.",
+                url.scheme()
+            ));
+        }
+    }
+    let (sender, receiver) = channel(options.queue_capacity);
 
-    spawn(run(url, sender).map(|result| {
+    spawn(run(urls, options, sender).map(|result| {
         if let Err(error) = result {
             error!(?error, "Error in task");
             std::process::abort();
@@ -100,115 +270,335 @@ pub fn start(url: Url) -> AnyResult<Receiver<Event>> {
     Ok(receiver)
 }
 
-/// Run block watcher with retries
-async fn run(url: Url, sender: Sender<Event>) -> AnyResult<()> {
-    let mut last = None;
+/// Fetch the chain id from the first provider that responds
+pub async fn chain_id(urls: &[Url], options: &Options) -> AnyResult<U256> {
+    let mut last_error = None;
+    for url in urls {
+        let result = async {
+            let (eth, sub) = connect(url).await?;
+            drop(sub);
+            Ok::<_, Error>(timeout(options.fetch_timeout, eth.chain_id()).await??)
+        };
+        match result.await {
+            Ok(chain_id) => return Ok(chain_id),
+            Err(error) => {
+                warn!(?error, %url, "Error fetching chain id");
+                last_error = Some(error);
+            }
+        }
+    }
+    Err(last_error.map_or_else(
+        || anyhow!("At least one ethereum provider is required."),
+        AnyError::from,
+    ))
+}
+
+/// Follow the longest chain across all providers
+async fn run(urls: Vec<Url>, options: Options, sender: Sender<Event>) -> AnyResult<()> {
+    // Start a task for each provider
+    let (heads_sender, mut heads) = mpsc::channel(options.queue_capacity);
+    let mut timestamps = vec![None; urls.len()];
+    for (provider, url) in urls.into_iter().enumerate() {
+        let heads_sender = heads_sender.clone();
+        let options = options.clone();
+        spawn(async move {
+            if let Err(error) = run_provider(provider, &url, &options, &heads_sender).await {
+                error!(?error, %url, "Ethereum provider failed, dropping provider");
+                PROVIDERS_FAILED.inc();
+            }
+        });
+    }
+    drop(heads_sender);
+
+    let mut history = History::load(options.history_size, options.history_file.clone())?;
     let mut retries = 0;
     loop {
-        let first = last.clone();
-        let result = run_once(&url, &sender, &mut last).await;
+        // Fetch next head from any of the providers
+        let block_timer = BLOCK_TIME.start_timer();
+        let Head {
+            provider,
+            eth,
+            header,
+        } = match heads.recv().await {
+            Some(head) => head,
+            None => return Err(anyhow!("All ethereum providers failed")),
+        };
+
+        // Skip stale heads if another provider is keeping up
+        let number = header.number.ok_or(Error::NumberMissing)?;
+        let timestamp = header_timestamp(&header);
+        timestamps[provider] = Some(timestamp);
+        if is_lagging(&options, &timestamps, provider) {
+            debug!(provider, ?number, "Provider is lagging, ignoring header");
+            STALE_HEADERS.inc();
+            continue;
+        }
+
+        // Use the first head as starting point
+        if history.last().is_none() {
+            emit(&sender, header.clone().into()).await?;
+            history.push(header);
+            save_history(&history);
+            continue;
+        }
+
+        // Skip if not on the longest known chain
+        let last_number = history.last().and_then(|last| last.number);
+        if last_number.unwrap_or_default() >= number {
+            debug!(provider, "Block is not on longest known chain, ignoring");
+            continue;
+        }
+        drop(block_timer);
+
+        // Send block, fetching any missing blocks from the same provider
+        let result = follow(&options, &eth, &mut history, &header, &sender).await;
+        save_history(&history);
+        match result {
+            Ok(()) => {
+                retries = 0;
+            }
+            Err(Error::ReceiverClosed) => return Err(Error::ReceiverClosed.into()),
+            Err(error) => {
+                error!(?error, provider, "Error following chain");
+
+                // Abort if maximum number of retries was exceeded
+                retries += 1;
+                if retries > options.max_tries {
+                    return Err(error).context("Maximum retries exceeded");
+                }
+            }
+        }
+    }
+}
+
+/// Follow a single provider with retries
+async fn run_provider(
+    provider: usize,
+    url: &Url,
+    options: &Options,
+    heads: &mpsc::Sender<Head>,
+) -> AnyResult<()> {
+    let mut retries = 0;
+    loop {
+        let mut progress = false;
+        let result = run_once(provider, url, options, heads, &mut progress).await;
         let error = match result {
             Ok(_) => return Ok(()),
             Err(e) => e,
         };
-        error!(?error, "Block fetch connection failed");
+        error!(?error, %url, "Block fetch connection failed");
 
         // Reset try counter if progress was made
-        if last != first {
+        if progress {
             retries = 0;
         }
 
         // Abort if maximum number of retries was exceeded
-        if retries > MAX_TRIES {
+        if retries > options.max_tries {
             return Err(error).context("Maximum retries exceeded");
         }
 
         // Jitter delay.
-        sleep(RETRY_DELAY).await;
+        sleep(retry_delay(options, retries)).await;
         retries += 1;
     }
 }
 
 /// Handle a single connection lifecycle
 async fn run_once(
+    provider: usize,
     url: &Url,
-    sender: &Sender<Event>,
-    last: &mut Option<BlockHeader>,
+    options: &Options,
+    heads: &mpsc::Sender<Head>,
+    progress: &mut bool,
 ) -> Result<(), Error> {
     // Connect to web3
     let (eth, mut sub) = connect(url).await?;
 
-    // Fetch latest block if we don't have a last block
-    if last.is_none() {
-        let latest = fetch_header(&eth, BlockNumber::Latest).await?;
-        // Send call returns error iif there are no receivers.
-        // See <https://docs.rs/tokio/1.10.0/tokio/sync/broadcast/error/struct.SendError.html>
-        let _result = sender.send(latest.clone().into());
-        *last = Some(latest);
-    }
-    let last = last.as_mut().unwrap();
+    // Fetch latest block to start with
+    let mut last = fetch_header(options, &eth, BlockNumber::Latest).await?;
+    send_head(provider, &eth, &last, heads).await?;
+    *progress = true;
 
     // Fetch blocks
-    fetch_loop(&eth, &mut sub, sender, last).await?;
-    Ok(())
+    loop {
+        let header = next_header(options, &eth, &mut sub).await?;
+        if header.hash == last.hash {
+            continue;
+        }
+        send_head(provider, &eth, &header, heads).await?;
+        last = header;
+    }
 }
 
-/// Create a new websocket connection
+/// Log, measure and forward a header received from a provider
+async fn send_head(
+    provider: usize,
+    eth: &Eth<Transport>,
+    header: &BlockHeader,
+    heads: &mpsc::Sender<Head>,
+) -> Result<(), Error> {
+    let number = header.number.ok_or(Error::NumberMissing)?;
+    let hash = header.hash.ok_or(Error::HashMissing)?;
+    let age = Utc::now() - header_timestamp(header);
+    debug!(provider, ?number, ?hash, ?header, ?age, "Received header");
+    BLOCK_HEADER_AGE.observe(age.to_std().unwrap_or_default().as_secs_f64());
+
+    heads
+        .send(Head {
+            provider,
+            eth: eth.clone(),
+            header: header.clone(),
+        })
+        .await
+        .map_err(|_| Error::EndOfStream)
+}
+
+/// Timestamp of a block header
+#[allow(clippy::cast_possible_wrap)]
+fn header_timestamp(header: &BlockHeader) -> DateTime<Utc> {
+    Utc.timestamp(header.timestamp.as_u64() as i64, 0)
+}
+
+/// Whether a head is older than [`Options::max_head_age`]
+fn is_stale(options: &Options, timestamp: DateTime<Utc>) -> bool {
+    (Utc::now() - timestamp)
+        .to_std()
+        .map_or(false, |age| age > options.max_head_age)
+}
+
+/// Whether the head of `provider` is stale while another provider's is not
+fn is_lagging(options: &Options, timestamps: &[Option<DateTime<Utc>>], provider: usize) -> bool {
+    timestamps[provider].map_or(false, |timestamp| is_stale(options, timestamp))
+        && timestamps.iter().flatten().any(|t| !is_stale(options, *t))
+}
+
+/// Exponential backoff with jitter. The delay doubles on every retry up to
+/// [`Options::max_retry_delay`] and is then randomized between half and the
+/// full delay.
+fn retry_delay(options: &Options, retries: usize) -> Duration {
+    let exponent = u32::try_from(retries).unwrap_or(u32::MAX).min(31);
+    let delay = options
+        .retry_delay
+        .saturating_mul(1 << exponent)
+        .min(options.max_retry_delay);
+    delay.mul_f64(thread_rng().gen_range(0.5..=1.0))
+}
+
+/// Create a new connection. WebSocket providers are subscribed to, HTTP
+/// providers are polled.
 async fn connect(
     url: &Url,
-) -> Result<(Eth<WebSocket>, SubscriptionStream<WebSocket, BlockHeader>), Error> {
+) -> Result<
+    (
+        Eth<Transport>,
+        Option<SubscriptionStream<WebSocket, BlockHeader>>,
+    ),
+    Error,
+> {
     CONNECTION_ATTEMPTS.inc();
-    let transport = WebSocket::new(url.as_str()).await?;
-    let eth = Eth::new(transport.clone());
-    let eth_subscribe = EthSubscribe::new(transport);
-    let sub = eth_subscribe.subscribe_new_heads().await?;
-    Ok((eth, sub))
+    if matches!(url.scheme(), "ws" | "wss") {
+        let transport = WebSocket::new(url.as_str()).await?;
+        let eth = Eth::new(Either::Left(transport.clone()));
+        let eth_subscribe = EthSubscribe::new(transport);
+        let sub = eth_subscribe.subscribe_new_heads().await?;
+        Ok((eth, Some(sub)))
+    } else {
+        let transport = Http::new(url.as_str())?;
+        Ok((Eth::new(Either::Right(transport)), None))
+    }
 }
 
-/// Wait and poll for new blocks in a loop.
-async fn fetch_loop(
-    eth: &Eth<WebSocket>,
-    sub: &mut SubscriptionStream<WebSocket, BlockHeader>,
+/// Send a new head, backfilling missing blocks and recovering from deep
+/// re-orgs where needed.
+async fn follow(
+    options: &Options,
+    eth: &Eth<Transport>,
+    history: &mut History,
+    latest: &BlockHeader,
     sender: &Sender<Event>,
-    last: &mut BlockHeader,
 ) -> Result<(), Error> {
-    loop {
-        // Fetch next block and skip if not latest
-        let block_timer = BLOCK_TIME.start_timer();
-        let header = next_header(eth, sub).await?;
-        let number = header.number.ok_or(Error::NumberMissing)?;
-        if last.number.unwrap_or_default() >= number {
-            debug!("Block is not on longest known chain, ignoring");
-            continue;
-        }
-        drop(block_timer);
+    let last = history.last().ok_or(Error::NotFound)?;
+    let last_number = last.number.ok_or(Error::NumberMissing)?.as_u64();
+    let number = latest.number.ok_or(Error::NumberMissing)?.as_u64();
+
+    // Backfill if more blocks are missing than a re-org would fetch
+    let mut result = Ok(());
+    if number.saturating_sub(last_number) > options.max_reorg as u64 {
+        result = backfill(options, eth, history, latest, sender).await;
+    }
+    if result.is_ok() {
+        result = send_with_reorgs(options, eth, history, latest, sender).await;
+    }
+    if let Err(Error::ReorgOverflow) = result {
+        warn!("Re-org exceeded max re-org depth, recovering");
+        result = recover(options, eth, history, latest, sender).await;
+    }
+    result
+}
 
-        // Log and measure block
-        let hash = header.hash.ok_or(Error::HashMissing)?;
-        #[allow(clippy::cast_possible_wrap)]
-        let timestamp = Utc.timestamp(header.timestamp.as_u64() as i64, 0);
-        let age = Utc::now() - timestamp;
-        debug!(?number, ?hash, ?header, ?age, "Received header");
-        BLOCK_HEADER_AGE.observe(age.to_std().unwrap_or_default().as_secs_f64());
+/// Send the blocks missing between the last emitted header and `latest`,
+/// fetched by number. At most [`Options::max_backfill`] blocks are sent, older
+/// missing blocks are skipped. The `latest` block itself is not sent.
+async fn backfill(
+    options: &Options,
+    eth: &Eth<Transport>,
+    history: &mut History,
+    latest: &BlockHeader,
+    sender: &Sender<Event>,
+) -> Result<(), Error> {
+    let last = history.last().ok_or(Error::NotFound)?;
+    let last_number = last.number.ok_or(Error::NumberMissing)?.as_u64();
+    let number = latest.number.ok_or(Error::NumberMissing)?.as_u64();
+
+    // Make sure the last emitted block is still canonical
+    let canonical = fetch_header(options, eth, BlockNumber::Number(last_number.into())).await?;
+    if canonical.hash != last.hash {
+        return Err(Error::ReorgOverflow);
+    }
 
-        // Send block
-        send_with_reorgs(eth, last, &header, sender).await?;
-        *last = header;
+    // Skip blocks beyond the maximum backfill
+    let mut start = last_number + 1;
+    if number.saturating_sub(start) > options.max_backfill as u64 {
+        start = number - options.max_backfill as u64;
+        warn!(
+            skipped = start - last_number - 1,
+            "Missing blocks exceed max backfill, skipping"
+        );
+        history.clear();
     }
+
+    // Send missing blocks
+    info!(from = start, to = number - 1, "Backfilling missing blocks");
+    for number in start..number {
+        let header = fetch_header(options, eth, BlockNumber::Number(number.into())).await?;
+
+        // Sanity check
+        if let Some(last) = history.last() {
+            if header.parent_hash != last.hash.unwrap() {
+                return Err(Error::InsaneParentHash);
+            }
+        }
+        history.push(header.clone());
+        BLOCKS_BACKFILLED.inc();
+        emit(sender, header.into()).await?;
+    }
+    Ok(())
 }
 
 /// Send a new block on the channel including any reorg events
 async fn send_with_reorgs(
-    eth: &Eth<WebSocket>,
-    last: &BlockHeader,
+    options: &Options,
+    eth: &Eth<Transport>,
+    history: &mut History,
     latest: &BlockHeader,
     sender: &Sender<Event>,
 ) -> Result<(), Error> {
-    let mut last = last.clone();
+    let mut last = history.last().ok_or(Error::NotFound)?.clone();
     let mut queue = vec![latest.clone()];
     let mut rewound = 0_usize;
     loop {
-        if queue.len() > MAX_REORG {
+        if queue.len() > options.max_reorg {
             return Err(Error::ReorgOverflow);
         }
         let end = queue.last().unwrap();
@@ -221,14 +611,13 @@ async fn send_with_reorgs(
             }
 
             // Rewind last to previous block (i.e. do a re-org)
-            // TODO: Emit re-org event
             info!("Re-org detected, rewinding latest block");
             rewound += 1;
-            last = fetch_header(eth, last.parent_hash).await?;
+            last = fetch_header(options, eth, last.parent_hash).await?;
         }
 
         // Fetch previous
-        let parent = fetch_header(eth, end.parent_hash).await?;
+        let parent = fetch_header(options, eth, end.parent_hash).await?;
         queue.push(parent);
     }
     #[allow(clippy::cast_precision_loss)]
@@ -239,9 +628,9 @@ async fn send_with_reorgs(
         BLOCKS_REWOUND.observe(rewound as f64);
 
         // Send re-org event
-        let _result = sender.send(Reorgable::Reorg {
-            block_height: last.number.unwrap().as_u64() + 1,
-        });
+        let block_height = last.number.unwrap().as_u64() + 1;
+        history.rewind(block_height);
+        emit(sender, Reorgable::Reorg { block_height }).await?;
     }
 
     // Send new headers to all receivers
@@ -255,28 +644,115 @@ async fn send_with_reorgs(
             return Err(Error::InsaneParentHash);
         }
         last = header.clone();
-
-        // Send call returns error iif there are no receivers.
-        // See <https://docs.rs/tokio/1.10.0/tokio/sync/broadcast/error/struct.SendError.html>
-        let _result = sender.send(header.into());
+        history.push(header.clone());
+        emit(sender, header.into()).await?;
     }
 
     Ok(())
 }
 
+/// Recover from a re-org deeper than [`Options::max_reorg`].
+///
+/// Searches the history for the most recent header still on the canonical
+/// chain, emits a re-org from there (or from the deepest known height if there
+/// is no common ancestor) and resyncs from `latest`.
+async fn recover(
+    options: &Options,
+    eth: &Eth<Transport>,
+    history: &mut History,
+    latest: &BlockHeader,
+    sender: &Sender<Event>,
+) -> Result<(), Error> {
+    DEEP_REORGS.inc();
+
+    // Binary search for the common ancestor. History headers are consecutive,
+    // so if a header is canonical all headers before it are too.
+    let (mut low, mut high) = (0, history.len());
+    while low < high {
+        let mid = (low + high) / 2;
+        let known = history.get(mid).unwrap();
+        let number = known.number.ok_or(Error::NumberMissing)?;
+        let canonical = match fetch_header(options, eth, BlockNumber::Number(number)).await {
+            Ok(header) => header.hash == known.hash,
+            Err(Error::NotFound) => false,
+            Err(error) => return Err(error),
+        };
+        if canonical {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    let block_height = if low > 0 {
+        // Re-org from the block after the common ancestor
+        let ancestor = history.get(low - 1).and_then(|header| header.number);
+        ancestor.unwrap_or_default().as_u64() + 1
+    } else {
+        // No common ancestor, re-org from the deepest known height
+        let deepest = history.first().and_then(|header| header.number);
+        deepest.unwrap_or_default().as_u64()
+    };
+    let last = history
+        .last()
+        .and_then(|header| header.number)
+        .unwrap_or_default()
+        .as_u64();
+
+    // Send re-org event if any emitted blocks are no longer canonical
+    if block_height <= last {
+        warn!(
+            block_height,
+            "Deep re-org detected, resyncing from latest block"
+        );
+        #[allow(clippy::cast_precision_loss)]
+        BLOCKS_REWOUND.observe((last + 1 - block_height) as f64);
+        emit(sender, Reorgable::Reorg { block_height }).await?;
+    } else {
+        info!("Too many missing blocks, resyncing from latest block");
+    }
+
+    // Resync from latest
+    history.clear();
+    history.push(latest.clone());
+    BLOCKS_RECEIVED.inc();
+    emit(sender, latest.clone().into()).await
+}
+
+/// Send an event, waiting while the channel is full
+async fn emit(sender: &Sender<Event>, event: Event) -> Result<(), Error> {
+    sender.send(event).await.map_err(|_| Error::ReceiverClosed)
+}
+
+/// Persist the header history, logging any errors
+fn save_history(history: &History) {
+    if let Err(error) = history.save() {
+        error!(?error, "Error saving header history");
+    }
+}
+
 /// Try fetch the next header. If no new header is found in time, return the
 /// last header.
 async fn next_header(
-    eth: &Eth<WebSocket>,
-    sub: &mut SubscriptionStream<WebSocket, BlockHeader>,
+    options: &Options,
+    eth: &Eth<Transport>,
+    sub: &mut Option<SubscriptionStream<WebSocket, BlockHeader>>,
 ) -> Result<BlockHeader, Error> {
+    // Without a subscription we can only poll.
+    let sub = match sub {
+        Some(sub) => sub,
+        None => {
+            sleep(options.poll_delay).await;
+            return fetch_header(options, eth, BlockNumber::Latest).await;
+        }
+    };
+
     // Note that [`StreamExt::next`] is cancellation safe. We will not lose data
     // if we drop futures. See <https://docs.rs/tokio/1.10.0/tokio/macro.select.html#cancellation-safety>
 
     // Try waiting on the stream.
     select! {
         next = sub.next() => return Ok(next.ok_or(Error::EndOfStream)??),
-        _ = sleep(POLL_DELAY) => {}
+        _ = sleep(options.poll_delay) => {}
     }
 
     // Fetch and return the latest header instead. This also acts as a guarantee
@@ -287,17 +763,18 @@ async fn next_header(
     // "web3::transports::ws: Sending a response to deallocated channel"
     select! {
         next = sub.next() => Ok(next.ok_or(Error::EndOfStream)??),
-        last = fetch_header(eth, BlockNumber::Latest) => last
+        last = fetch_header(options, eth, BlockNumber::Latest) => last
     }
 }
 
 async fn fetch_header<B: Into<BlockId> + Send>(
-    eth: &Eth<WebSocket>,
+    options: &Options,
+    eth: &Eth<Transport>,
     block_id: B,
 ) -> Result<BlockHeader, Error> {
     let _timer = BLOCK_HEADER_LATENCY.start_timer(); // Observe on drop
     let request = eth.block(block_id.into());
-    let block = timeout(FETCH_TIMEOUT, request)
+    let block = timeout(options.fetch_timeout, request)
         .await??
         .ok_or(Error::NotFound)?;
     let header = block_to_header(block);
@@ -330,3 +807,201 @@ fn block_to_header(block: Block<H256>) -> BlockHeader {
         nonce:             block.nonce,
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server, StatusCode,
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// Chain served by [`mock_provider`]
+    #[derive(Debug, Default)]
+    pub(crate) struct MockChain {
+        pub blocks:    Vec<Block<H256>>,
+        pub safe:      Option<u64>,
+        pub finalized: Option<u64>,
+    }
+
+    impl MockChain {
+        /// Chain of `len` consecutive blocks
+        pub(crate) fn new(len: u64) -> Arc<Mutex<Self>> {
+            let mut chain = Self::default();
+            chain.extend(len);
+            Arc::new(Mutex::new(chain))
+        }
+
+        /// Add blocks timestamped now until the chain has `len` blocks
+        pub(crate) fn extend(&mut self, len: u64) {
+            let timestamp = u64::try_from(Utc::now().timestamp()).unwrap();
+            for number in self.blocks.len() as u64..len {
+                self.blocks.push(Block {
+                    hash: Some(H256::from_low_u64_be(number + 1)),
+                    parent_hash: H256::from_low_u64_be(number),
+                    number: Some(number.into()),
+                    timestamp: timestamp.into(),
+                    ..Block::default()
+                });
+            }
+        }
+
+        pub(crate) fn header(&self, number: u64) -> BlockHeader {
+            block_to_header(self.blocks[number as usize].clone())
+        }
+
+        fn respond(&self, method: &str, params: &Value) -> Value {
+            let number = match (method, params[0].as_str()) {
+                ("eth_chainId", _) => return json!("0x1"),
+                ("eth_getBlockByHash", Some(hash)) => {
+                    let hash: H256 = hash.parse().unwrap();
+                    let block = self.blocks.iter().find(|block| block.hash == Some(hash));
+                    return json!(block);
+                }
+                ("eth_getBlockByNumber", Some("latest")) => self.blocks.len().checked_sub(1),
+                ("eth_getBlockByNumber", Some("safe")) => self.safe.map(|n| n as usize),
+                ("eth_getBlockByNumber", Some("finalized")) => self.finalized.map(|n| n as usize),
+                ("eth_getBlockByNumber", Some(number)) => {
+                    usize::from_str_radix(number.trim_start_matches("0x"), 16).ok()
+                }
+                _ => panic!("Unexpected request {} {}", method, params),
+            };
+            json!(number.and_then(|number| self.blocks.get(number)))
+        }
+    }
+
+    /// Serve `chain` over JSON-RPC. Returns the provider url and a switch to
+    /// make the provider fail all requests.
+    pub(crate) fn mock_provider(chain: &Arc<Mutex<MockChain>>) -> (Url, Arc<AtomicBool>) {
+        let down = Arc::new(AtomicBool::new(false));
+        let (chain, service_down) = (chain.clone(), down.clone());
+        let make_service = make_service_fn(move |_| {
+            let (chain, down) = (chain.clone(), service_down.clone());
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request: Request<Body>| {
+                    let (chain, down) = (chain.clone(), down.clone());
+                    async move {
+                        if down.load(Ordering::SeqCst) {
+                            return Response::builder()
+                                .status(StatusCode::SERVICE_UNAVAILABLE)
+                                .body(Body::empty());
+                        }
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let request: Value = serde_json::from_slice(&body).unwrap();
+                        let method = request["method"].as_str().unwrap();
+                        let result = chain.lock().unwrap().respond(method, &request["params"]);
+                        let response =
+                            json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+                        Response::builder().body(Body::from(response.to_string()))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr()).parse().unwrap();
+        spawn(server);
+        (url, down)
+    }
+
+    #[test]
+    fn test_event_proto() {
+        let header = MockChain::new(3).lock().unwrap().header(2);
+        for event in [Event::Event(header), Event::Reorg { block_height: 1 }] {
+            let proto = event.clone().into_proto();
+            assert_eq!(Event::try_from_proto(proto), Ok(event));
+        }
+        let header = Event::Event(MockChain::new(3).lock().unwrap().header(2)).into_proto();
+        assert_eq!(header.block_height, 2);
+        assert_eq!(
+            Event::try_from_proto(EventProto::default()),
+            Err(MissingField("event"))
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let options = Options::default();
+        for retries in 0..100 {
+            let delay = retry_delay(&options, retries);
+            assert!(delay <= options.max_retry_delay);
+            assert!(delay >= options.retry_delay / 2);
+        }
+        assert!(retry_delay(&options, 3) >= options.retry_delay * 4);
+    }
+
+    #[test]
+    fn test_is_lagging() {
+        let options = Options::default();
+        let fresh = Some(Utc::now());
+        let stale = Some(Utc::now() - chrono::Duration::minutes(5));
+        assert!(is_lagging(&options, &[stale, fresh], 0));
+        assert!(!is_lagging(&options, &[stale, fresh], 1));
+        assert!(!is_lagging(&options, &[fresh, fresh], 0));
+
+        // Stale heads are used if no provider is keeping up
+        assert!(!is_lagging(&options, &[stale, stale], 0));
+        assert!(!is_lagging(&options, &[stale, None], 0));
+    }
+
+    #[tokio::test]
+    async fn test_backfill_beyond_queue_capacity() {
+        let options = Options {
+            queue_capacity: 2,
+            max_reorg: 1,
+            ..Options::default()
+        };
+        let chain = MockChain::new(50);
+        let (eth, _) = connect(&mock_provider(&chain).0).await.unwrap();
+        let mut history = History::load(options.history_size, None).unwrap();
+        history.push(chain.lock().unwrap().header(0));
+        let latest = chain.lock().unwrap().header(49);
+        let (sender, mut receiver) = channel(options.queue_capacity);
+        let follow =
+            spawn(async move { follow(&options, &eth, &mut history, &latest, &sender).await });
+
+        // All blocks are received in order, none are dropped
+        for number in 1..50 {
+            let header = chain.lock().unwrap().header(number);
+            assert_eq!(receiver.recv().await, Some(Reorgable::Event(header)));
+        }
+        follow.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failover() {
+        let options = Options {
+            poll_delay: Duration::from_millis(10),
+            retry_delay: Duration::from_millis(10),
+            max_tries: 0,
+            ..Options::default()
+        };
+        let chain = MockChain::new(4);
+        let (first, first_down) = mock_provider(&chain);
+        let (second, _) = mock_provider(&chain);
+        let failed = PROVIDERS_FAILED.get();
+        let (sender, mut receiver) = channel(options.queue_capacity);
+        spawn(run(vec![first, second], options, sender));
+        let header = chain.lock().unwrap().header(3);
+        assert_eq!(receiver.recv().await, Some(Reorgable::Event(header)));
+
+        // Blocks keep coming from the second provider
+        first_down.store(true, Ordering::SeqCst);
+        chain.lock().unwrap().extend(8);
+        for number in 4..8 {
+            let header = chain.lock().unwrap().header(number);
+            assert_eq!(receiver.recv().await, Some(Reorgable::Event(header)));
+        }
+        timeout(Duration::from_secs(5), async {
+            while PROVIDERS_FAILED.get() == failed {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -1,18 +1,18 @@
 #![warn(clippy::all, clippy::pedantic, clippy::cargo, clippy::nursery)]
 
 mod allocator;
-mod logging;
 mod prometheus;
 mod shutdown;
 
 use anyhow::{Context as _, Result as AnyResult};
-use block_watcher::producer::Producer;
+use block_watcher::producer::{Producer, Topics};
 use structopt::StructOpt;
 use tokio::{runtime, spawn, sync::oneshot};
-use tracing::info;
+use tracing::{error, info};
+use types::logging::LogOptions;
 use url::Url;
 
-use self::{allocator::Allocator, logging::LogOptions};
+use self::allocator::Allocator;
 
 const VERSION: &str = concat!(
     env!("CARGO_PKG_VERSION"),
@@ -48,20 +48,20 @@ struct Options {
     pub prometheus: prometheus::Options,
     #[structopt(flatten)]
     app:            types::Options,
-    #[structopt(
-        long,
-        env = "BLOCK_WATCHER_TOPIC",
-        default_value = "block_watcher_events"
-    )]
-    topic:          String,
-    /// Ethereum connection string.
+    #[structopt(flatten)]
+    watcher:        block_watcher::Options,
+    #[structopt(flatten)]
+    topics:         Topics,
+    /// Comma separated list of Ethereum connection strings (ws, wss, http or
+    /// https). The longest valid chain across all of them is followed.
     #[structopt(
         short,
         long,
         env = "ETHEREUM",
+        use_delimiter = true,
         default_value = "wss://eth-mainnet.ws.alchemyapi.io/v2/Tv2L-c59Mti0z9k0gnKBufinC6Ac86M-"
     )]
-    pub ethereum:   Url,
+    pub ethereum:   Vec<Url>,
 }
 
 fn main() -> AnyResult<()> {
@@ -73,7 +73,11 @@ fn main() -> AnyResult<()> {
     ALLOCATOR.start_metering();
 
     // Start log system
-    options.log.init()?;
+    options.log.init(
+        env!("CARGO_CRATE_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        env!("COMMIT_SHA"),
+    )?;
 
     // Launch Tokio runtime
     runtime::Builder::new_multi_thread()
@@ -91,8 +95,11 @@ fn main() -> AnyResult<()> {
             });
 
             spawn(async {
-                let producer = Producer::new(options.app, options.topic).await.unwrap();
-                let _ = producer.start(options.ethereum).await;
+                let producer = Producer::new(options.app, options.topics).await.unwrap();
+                if let Err(error) = producer.start(options.ethereum, options.watcher).await {
+                    error!(?error, "Error producing block events");
+                    std::process::abort();
+                }
             });
 
             shutdown.await
@@ -1,44 +1,175 @@
 use anyhow::Error as AnyError;
-use futures::TryStreamExt;
-use tokio_stream::wrappers::BroadcastStream;
+use futures::{StreamExt, TryStreamExt};
+use structopt::StructOpt;
+use tokio::try_join;
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::info;
-use types::{proto::BlockHeader as BlockHeaderProto, IntoProto, Kafka, KafkaProducer, Options};
+use types::{
+    proto::{
+        zeroex::reorgable::Event as EventProto, BlockData as BlockDataProto,
+        BlockHeader as BlockHeaderProto,
+    },
+    IntoProto, Kafka, KafkaProducer, Options,
+};
 use url::Url;
 
-use super::{start as start_watching, AnyResult, Reorgable};
+use super::{
+    chain_id, start as start_watching, start_finality, AnyResult, BlockDataFetcher, Finality,
+    FinalityMode, Options as WatcherOptions, Reorgable,
+};
 
-// Maximum number of blocks to process concurrently
+// Maximum number of blocks to fetch data for concurrently
 const MAX_CONCURRENT_BLOCKS: usize = 10;
 
-pub async fn start(options: Options, url: Url, topic: String) -> AnyResult<()> {
-    let block_watcher = Producer::new(options, topic).await?;
-    block_watcher.start(url).await?;
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct Topics {
+    /// Topic for block headers and re-orgs, see `reorgable.proto`
+    #[structopt(
+        long,
+        env = "BLOCK_WATCHER_TOPIC",
+        default_value = "block_watcher_events"
+    )]
+    pub topic:            String,
+    /// Topic for safe block headers, if finality is tracked
+    #[structopt(long, env = "SAFE_TOPIC", default_value = "block_watcher_safe_events")]
+    pub safe_topic:       String,
+    /// Topic for finalized block headers, if finality is tracked
+    #[structopt(
+        long,
+        env = "FINALIZED_TOPIC",
+        default_value = "block_watcher_finalized_events"
+    )]
+    pub finalized_topic:  String,
+    /// Topic for block logs and receipts. Only published if set.
+    #[structopt(long, env = "BLOCK_DATA_TOPIC")]
+    pub block_data_topic: Option<String>,
+}
+
+pub async fn start(
+    options: Options,
+    watcher_options: WatcherOptions,
+    urls: Vec<Url>,
+    topics: Topics,
+) -> AnyResult<()> {
+    let block_watcher = Producer::new(options, topics).await?;
+    block_watcher.start(urls, watcher_options).await?;
     Ok(())
 }
 
-pub struct Producer(KafkaProducer<BlockHeaderProto>);
+pub struct Producer {
+    latest:    KafkaProducer<EventProto>,
+    safe:      KafkaProducer<BlockHeaderProto>,
+    finalized: KafkaProducer<BlockHeaderProto>,
+    data:      Option<KafkaProducer<BlockDataProto>>,
+}
 
 impl Producer {
-    pub async fn new(options: Options, topic: String) -> AnyResult<Self> {
+    pub async fn new(options: Options, topics: Topics) -> AnyResult<Self> {
         let kafka = Kafka::new(options).await?;
-        Ok(Self(kafka.new_producer(&topic).await?))
+        Ok(Self {
+            latest:    kafka.new_producer(&topics.topic).await?,
+            safe:      kafka.new_producer(&topics.safe_topic).await?,
+            finalized: kafka.new_producer(&topics.finalized_topic).await?,
+            data:      match &topics.block_data_topic {
+                Some(topic) => Some(kafka.new_producer(topic).await?),
+                None => None,
+            },
+        })
+    }
+
+    pub async fn start(&self, eth_urls: Vec<Url>, options: WatcherOptions) -> AnyResult<()> {
+        // Events are keyed by chain id, so they stay ordered per chain.
+        let key = chain_id(&eth_urls, &options).await?.to_string();
+        info!("Publishing block events with key {}", key);
+        let finality = async {
+            if options.finality == FinalityMode::Disabled {
+                return Ok(());
+            }
+            self.send_finality(eth_urls.clone(), options.clone(), &key)
+                .await
+        };
+        try_join!(
+            self.send_blocks(eth_urls.clone(), options.clone(), &key),
+            finality
+        )?;
+        Ok(())
     }
 
-    pub async fn start(&self, eth_url: Url) -> AnyResult<()> {
-        let block_stream = BroadcastStream::new(start_watching(eth_url)?);
+    async fn send_blocks(
+        &self,
+        eth_urls: Vec<Url>,
+        options: WatcherOptions,
+        key: &str,
+    ) -> AnyResult<()> {
+        let fetcher = self
+            .data
+            .as_ref()
+            .map(|_| BlockDataFetcher::new(eth_urls.clone(), options.clone()));
+        let fetcher = &fetcher;
+        let block_stream = ReceiverStream::new(start_watching(eth_urls, options)?);
+        // Logs and receipts are fetched concurrently, events are sent in order
         block_stream
-            .map_err(AnyError::from)
-            .try_for_each_concurrent(Some(MAX_CONCURRENT_BLOCKS), move |event| {
+            .map(move |event| {
                 async move {
-                    let header = match event {
-                        Reorgable::Reorg { .. } => return Ok(()),
-                        Reorgable::Event(header) => header,
+                    let block_data = match (&event, fetcher) {
+                        (Reorgable::Event(header), Some(fetcher)) => {
+                            Some(fetcher.fetch(header.clone()).await?)
+                        }
+                        _ => None,
                     };
+                    Ok::<_, AnyError>((event, block_data))
+                }
+            })
+            .buffered(MAX_CONCURRENT_BLOCKS)
+            .try_for_each(move |(event, block_data)| {
+                async move {
+                    match &event {
+                        Reorgable::Event(header) => {
+                            info!(
+                                "Sending block header with number = {:?} to Kafka",
+                                header.number
+                            );
+                        }
+                        Reorgable::Reorg { block_height } => {
+                            info!(
+                                "Sending re-org from block number = {} to Kafka",
+                                block_height
+                            );
+                        }
+                    }
+                    self.latest.send_keyed(key, &event.into_proto()).await?;
+
+                    // Send logs and receipts
+                    if let (Some(block_data), Some(data)) = (block_data, &self.data) {
+                        data.send_keyed(key, &block_data.into_proto()).await?;
+                    }
+                    Ok(())
+                }
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn send_finality(
+        &self,
+        eth_urls: Vec<Url>,
+        options: WatcherOptions,
+        key: &str,
+    ) -> AnyResult<()> {
+        let finality_stream = ReceiverStream::new(start_finality(eth_urls, options)?);
+        finality_stream
+            .map(Ok::<_, AnyError>)
+            .try_for_each(move |(finality, header)| {
+                async move {
                     info!(
-                        "Sending block header with number = {:?} to Kafka",
-                        header.number
+                        "Sending {:?} block header with number = {:?} to Kafka",
+                        finality, header.number
                     );
-                    self.0.send(&header.into_proto()).await?;
+                    let producer = match finality {
+                        Finality::Safe => &self.safe,
+                        Finality::Finalized => &self.finalized,
+                    };
+                    producer.send_keyed(key, &header.into_proto()).await?;
                     Ok(())
                 }
             })
@@ -22,6 +22,22 @@ pub static CONNECTION_ATTEMPTS: Lazy<IntCounter> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static PROVIDERS_FAILED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "providers_failed",
+        "Number of Ethereum providers dropped after exceeding retries."
+    )
+    .unwrap()
+});
+
+pub static STALE_HEADERS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "stale_headers",
+        "Number of headers ignored because the provider is lagging."
+    )
+    .unwrap()
+});
+
 pub static BLOCKS_REWOUND: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "blocks_rewound",
@@ -31,6 +47,22 @@ pub static BLOCKS_REWOUND: Lazy<Histogram> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static DEEP_REORGS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "deep_reorgs",
+        "Number of re-orgs exceeding the max re-org depth."
+    )
+    .unwrap()
+});
+
+pub static BLOCKS_BACKFILLED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "blocks_backfilled",
+        "Number of missing blocks fetched by number and sent."
+    )
+    .unwrap()
+});
+
 pub static BLOCKS_ADDED: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "blocks_added",
@@ -56,6 +88,13 @@ pub static BLOCK_HEADER_LATENCY: Lazy<Histogram> = Lazy::new(|| {
     )
     .unwrap()
 });
+pub static BLOCK_DATA_LATENCY: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "block_data_latency",
+        "The latency to request logs and receipts of a block."
+    )
+    .unwrap()
+});
 pub static BLOCK_HEADER_AGE: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "block_header_age",
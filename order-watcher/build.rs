@@ -41,6 +41,17 @@ fn main() -> Result<()> {
         "cargo:rustc-env=TARGET={}",
         var("TARGET").context("Fetching environment variable TARGET")?
     );
+
+    // Generate the gRPC service only, the messages are in the `types` crate
+    println!("cargo:rerun-if-changed=../types/protobuf");
+    tonic_build::configure()
+        .build_client(false)
+        .extern_path(".web3", "::types::proto")
+        .extern_path(".zeroex", "::types::proto::zeroex")
+        .compile(&["../types/protobuf/zeroex/order_service.proto"], &[
+            "../types/protobuf",
+        ])
+        .context("Error generating gRPC service")?;
     Ok(())
 }
 
@@ -0,0 +1 @@
+ALTER TABLE signed_orders_v4 DROP COLUMN taker_asset_filled_amount;
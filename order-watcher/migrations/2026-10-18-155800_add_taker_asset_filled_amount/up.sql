@@ -0,0 +1,3 @@
+-- Total taker amount filled, used to tell fills apart from balance and
+-- allowance changes
+ALTER TABLE signed_orders_v4 ADD COLUMN taker_asset_filled_amount varchar NOT NULL DEFAULT '0';
@@ -0,0 +1,12 @@
+-- API keys, identified by the hex encoded SHA-256 hash of the key.
+--
+-- `scopes` is a comma separated list of `submit`, `read` and `stream`, for
+-- example 'submit,read'. `rate` is in requests per second; keys without one
+-- get the per-IP limit instead.
+CREATE TABLE api_keys (
+    key_hash varchar PRIMARY KEY,
+    name varchar NOT NULL,
+    scopes varchar NOT NULL,
+    rate double precision NULL,
+    burst integer NULL
+);
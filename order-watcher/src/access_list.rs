@@ -0,0 +1,172 @@
+//! Token and maker allow and deny lists, loaded from a JSON file and reloaded
+//! when it changes.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context as _, Result as AnyResult};
+use serde::Deserialize;
+use tracing::{error, info};
+use web3::types::Address;
+
+use crate::{
+    orders::{Error, LimitOrder},
+    require,
+};
+
+/// How often the file is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AccessList {
+    /// If not empty, only orders between these tokens are accepted
+    #[serde(default)]
+    pub allowed_tokens: HashSet<Address>,
+    #[serde(default)]
+    pub denied_tokens:  HashSet<Address>,
+    #[serde(default)]
+    pub denied_makers:  HashSet<Address>,
+}
+
+impl AccessList {
+    pub fn check(&self, order: &LimitOrder) -> Result<(), Error> {
+        require!(
+            !self.denied_makers.contains(&order.maker),
+            Error::DeniedMaker
+        );
+        for token in [order.maker_token, order.taker_token] {
+            require!(self.is_token_allowed(token), Error::UnsupportedToken);
+        }
+        Ok(())
+    }
+
+    fn is_token_allowed(&self, token: Address) -> bool {
+        (self.allowed_tokens.is_empty() || self.allowed_tokens.contains(&token))
+            && !self.denied_tokens.contains(&token)
+    }
+}
+
+/// The current [`AccessList`], shared between clones. Accepts all orders if
+/// no file is configured.
+#[derive(Clone, Debug, Default)]
+pub struct SharedAccessList {
+    current: Arc<RwLock<Arc<AccessList>>>,
+}
+
+impl SharedAccessList {
+    /// Load the access list from `path` and reload it in the background when
+    /// the file changes. Invalid updates are logged and ignored.
+    pub async fn load(path: Option<PathBuf>) -> AnyResult<Self> {
+        let shared = Self::default();
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(shared),
+        };
+        let (list, mut modified) = read(&path).await?;
+        shared.set(list);
+        tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    let changed = tokio::fs::metadata(&path)
+                        .await
+                        .and_then(|metadata| metadata.modified())
+                        .map_or(true, |time| time != modified);
+                    if !changed {
+                        continue;
+                    }
+                    match read(&path).await {
+                        Ok((list, time)) => {
+                            modified = time;
+                            shared.set(list);
+                        }
+                        Err(error) => error!(?error, "Error reloading access list"),
+                    }
+                }
+            }
+        });
+        Ok(shared)
+    }
+
+    pub fn get(&self) -> Arc<AccessList> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn check(&self, order: &LimitOrder) -> Result<(), Error> {
+        self.get().check(order)
+    }
+
+    fn set(&self, list: AccessList) {
+        info!(
+            allowed_tokens = list.allowed_tokens.len(),
+            denied_tokens = list.denied_tokens.len(),
+            denied_makers = list.denied_makers.len(),
+            "Loaded access list"
+        );
+        *self.current.write().unwrap() = Arc::new(list);
+    }
+}
+
+async fn read(path: &Path) -> AnyResult<(AccessList, SystemTime)> {
+    let modified = tokio::fs::metadata(path)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Error reading {}", path.display()))?;
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Error reading {}", path.display()))?;
+    let list = serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid access list in {}", path.display()))?;
+    Ok((list, modified))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_access_list() {
+        let list: AccessList = serde_json::from_str(
+            r#"{
+                "allowedTokens": ["0x0101010101010101010101010101010101010101", "0x0202020202020202020202020202020202020202"],
+                "deniedMakers": ["0x0303030303030303030303030303030303030303"]
+            }"#,
+        )
+        .unwrap();
+        let order = LimitOrder {
+            maker: Address::repeat_byte(4),
+            maker_token: Address::repeat_byte(1),
+            taker_token: Address::repeat_byte(2),
+            ..LimitOrder::default()
+        };
+        assert!(list.check(&order).is_ok());
+        assert!(matches!(
+            list.check(&LimitOrder {
+                taker_token: Address::repeat_byte(5),
+                ..order
+            }),
+            Err(Error::UnsupportedToken)
+        ));
+        assert!(matches!(
+            list.check(&LimitOrder {
+                maker: Address::repeat_byte(3),
+                ..order
+            }),
+            Err(Error::DeniedMaker)
+        ));
+
+        let denied = AccessList {
+            denied_tokens: [Address::repeat_byte(2)].iter().copied().collect(),
+            ..AccessList::default()
+        };
+        assert!(matches!(denied.check(&order), Err(Error::UnsupportedToken)));
+        assert!(AccessList::default().check(&order).is_ok());
+    }
+}
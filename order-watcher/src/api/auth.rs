@@ -0,0 +1,333 @@
+//! API key authentication
+//!
+//! Keys are sent in the `0x-api-key` header (or gRPC metadata). If no key
+//! source is configured, all requests are allowed without a key. Only the
+//! SHA-256 hashes of the keys are stored, and the sources are reloaded
+//! periodically.
+
+use core::time::Duration;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, Context as _, Error as AnyError, Result as AnyResult};
+use serde::Deserialize;
+use sha2::{Digest as _, Sha256};
+use structopt::StructOpt;
+use tracing::{error, info};
+
+use super::{rate_limit::RateLimiter, Error};
+use crate::database::{ApiKeyRecord, Database};
+
+pub const API_KEY_HEADER: &str = "0x-api-key";
+
+/// Burst of keys with a rate but no burst
+const DEFAULT_BURST: u32 = 10;
+
+/// How often the key sources are reloaded
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, PartialEq, Debug, StructOpt)]
+pub struct Options {
+    /// JSON file with an array of API keys, each with a `key_hash` (hex
+    /// SHA-256 of the key), `name`, `scopes` and optionally a `rate` and
+    /// `burst` quota
+    #[structopt(long, env = "API_KEYS_FILE")]
+    pub api_keys_file: Option<PathBuf>,
+
+    /// Load API key hashes from the `api_keys` database table
+    #[structopt(long, env = "API_KEYS_DATABASE")]
+    pub api_keys_database: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Submit orders
+    Submit,
+    /// Validate and query orders
+    Read,
+    /// Stream order events
+    Stream,
+}
+
+impl FromStr for Scope {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "submit" => Ok(Self::Submit),
+            "read" => Ok(Self::Read),
+            "stream" => Ok(Self::Stream),
+            _ => Err(anyhow!("Invalid API key scope: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+pub struct ApiKey {
+    /// Hex encoded SHA-256 hash of the key, see [`hash_key`]
+    pub key_hash: String,
+    /// Name of the key owner, used in logs and metric labels
+    pub name:     String,
+    pub scopes:   Vec<Scope>,
+    /// Requests per second, the per-IP limit applies instead if unset
+    #[serde(default)]
+    pub rate:     Option<f64>,
+    #[serde(default)]
+    pub burst:    Option<u32>,
+}
+
+impl TryFrom<ApiKeyRecord> for ApiKey {
+    type Error = AnyError;
+
+    fn try_from(record: ApiKeyRecord) -> AnyResult<Self> {
+        let scopes = record
+            .scopes
+            .split(',')
+            .map(str::trim)
+            .filter(|scope| !scope.is_empty())
+            .map(Scope::from_str)
+            .collect::<AnyResult<_>>()
+            .with_context(|| format!("Invalid scopes of API key {}", record.name))?;
+        Ok(Self {
+            key_hash: record.key_hash,
+            name: record.name,
+            scopes,
+            rate: record.rate,
+            burst: record.burst.map(u32::try_from).transpose()?,
+        })
+    }
+}
+
+/// Hash a key for lookup, see [`ApiKey::key_hash`]
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// The authenticated client of a request
+pub struct Client {
+    /// The key name, or `none` if authentication is disabled
+    pub name: String,
+    quota:    Option<Arc<RateLimiter<()>>>,
+}
+
+impl Client {
+    /// Take a request from the key quota, `None` if the key has no quota.
+    pub fn check_quota(&self) -> Option<bool> {
+        self.quota.as_ref().map(|quota| quota.check((), 1))
+    }
+}
+
+type Keys = HashMap<String, (ApiKey, Option<Arc<RateLimiter<()>>>)>;
+
+#[derive(Debug)]
+pub struct Auth {
+    enabled: bool,
+    /// Keys by their hash
+    keys:    RwLock<Keys>,
+}
+
+impl Auth {
+    pub fn new(enabled: bool, keys: Vec<ApiKey>) -> Self {
+        let auth = Self {
+            enabled,
+            keys: RwLock::default(),
+        };
+        auth.set(keys);
+        auth
+    }
+
+    /// Load the keys from the configured sources and reload them in the
+    /// background. Invalid updates are logged and ignored.
+    pub async fn load(options: &Options, database: &Database) -> AnyResult<Arc<Self>> {
+        let enabled = options.api_keys_file.is_some() || options.api_keys_database;
+        let auth = Arc::new(Self::new(enabled, vec![]));
+        if !enabled {
+            return Ok(auth);
+        }
+        let keys = read(options, database).await?;
+        info!(keys = keys.len(), "Loaded API keys");
+        auth.set(keys);
+        tokio::spawn({
+            let (auth, options, database) = (auth.clone(), options.clone(), database.clone());
+            async move {
+                let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+                // The first tick completes immediately
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    match read(&options, &database).await {
+                        Ok(keys) => auth.set(keys),
+                        Err(error) => error!(?error, "Error reloading API keys"),
+                    }
+                }
+            }
+        });
+        Ok(auth)
+    }
+
+    /// Replace the keys. Quotas of keys with unchanged limits are kept.
+    fn set(&self, keys: Vec<ApiKey>) {
+        let mut current = self.keys.write().unwrap();
+        let keys = keys
+            .into_iter()
+            .map(|key| {
+                let key_hash = key.key_hash.to_lowercase();
+                let quota = match current.remove(&key_hash) {
+                    Some((old, quota)) if (old.rate, old.burst) == (key.rate, key.burst) => quota,
+                    _ => {
+                        key.rate.map(|rate| {
+                            Arc::new(RateLimiter::new(rate, key.burst.unwrap_or(DEFAULT_BURST)))
+                        })
+                    }
+                };
+                (key_hash, (key, quota))
+            })
+            .collect();
+        *current = keys;
+    }
+
+    /// Check that `key` is known and has `scope`.
+    pub fn authorize(&self, key: Option<&str>, scope: Scope) -> Result<Client, Error> {
+        if !self.enabled {
+            return Ok(Client {
+                name:  "none".to_string(),
+                quota: None,
+            });
+        }
+        let keys = self.keys.read().unwrap();
+        let (api_key, quota) = key
+            .and_then(|key| keys.get(&hash_key(key)))
+            .ok_or(Error::Unauthorized)?;
+        if !api_key.scopes.contains(&scope) {
+            return Err(Error::Forbidden(scope));
+        }
+        Ok(Client {
+            name:  api_key.name.clone(),
+            quota: quota.clone(),
+        })
+    }
+}
+
+/// Read the keys from the configured sources.
+async fn read(options: &Options, database: &Database) -> AnyResult<Vec<ApiKey>> {
+    let mut keys = vec![];
+    if let Some(path) = &options.api_keys_file {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Error reading {}", path.display()))?;
+        let file_keys: Vec<ApiKey> = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid API keys in {}", path.display()))?;
+        keys.extend(file_keys);
+    }
+    if options.api_keys_database {
+        for record in database.get_api_keys().await? {
+            keys.push(ApiKey::try_from(record)?);
+        }
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys(json: &str) -> Vec<ApiKey> {
+        let json = json
+            .replace("secret", &hash_key("secret"))
+            .replace("reader", &hash_key("reader"));
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_hash_key() {
+        assert_eq!(
+            hash_key("secret"),
+            "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"
+        );
+    }
+
+    #[test]
+    fn test_authorize() {
+        let keys = keys(
+            r#"[
+                {"key_hash": "secret", "name": "partner", "scopes": ["submit", "read"], "rate": 1.0, "burst": 1},
+                {"key_hash": "reader", "name": "reader", "scopes": ["read"]}
+            ]"#,
+        );
+        let auth = Auth::new(true, keys);
+        assert!(matches!(
+            auth.authorize(None, Scope::Read),
+            Err(Error::Unauthorized)
+        ));
+        assert!(matches!(
+            auth.authorize(Some("wrong"), Scope::Read),
+            Err(Error::Unauthorized)
+        ));
+        // Keys are looked up by hash, not by name or the hash itself
+        assert!(matches!(
+            auth.authorize(Some("partner"), Scope::Read),
+            Err(Error::Unauthorized)
+        ));
+        assert!(matches!(
+            auth.authorize(Some(&hash_key("secret")), Scope::Read),
+            Err(Error::Unauthorized)
+        ));
+        assert!(matches!(
+            auth.authorize(Some("reader"), Scope::Submit),
+            Err(Error::Forbidden(Scope::Submit))
+        ));
+        let client = auth.authorize(Some("secret"), Scope::Submit).unwrap();
+        assert_eq!(client.name, "partner");
+        assert_eq!(client.check_quota(), Some(true));
+        assert_eq!(client.check_quota(), Some(false));
+        let client = auth.authorize(Some("reader"), Scope::Read).unwrap();
+        assert_eq!(client.check_quota(), None);
+
+        let disabled = Auth::new(false, vec![]);
+        assert_eq!(
+            disabled.authorize(None, Scope::Stream).unwrap().name,
+            "none"
+        );
+    }
+
+    #[test]
+    fn test_reload() {
+        let auth = Auth::new(
+            true,
+            keys(
+                r#"[{"key_hash": "secret", "name": "partner", "scopes": ["read"], "rate": 1.0, "burst": 1}]"#,
+            ),
+        );
+        let client = auth.authorize(Some("secret"), Scope::Read).unwrap();
+        assert_eq!(client.check_quota(), Some(true));
+
+        // Unchanged limits keep their quota, new scopes apply
+        auth.set(keys(
+            r#"[{"key_hash": "secret", "name": "partner", "scopes": ["read", "submit"], "rate": 1.0, "burst": 1}]"#,
+        ));
+        let client = auth.authorize(Some("secret"), Scope::Submit).unwrap();
+        assert_eq!(client.check_quota(), Some(false));
+
+        // Changed limits start with a full quota
+        auth.set(keys(
+            r#"[{"key_hash": "secret", "name": "partner", "scopes": ["read"], "rate": 1.0, "burst": 2}]"#,
+        ));
+        let client = auth.authorize(Some("secret"), Scope::Read).unwrap();
+        assert_eq!(client.check_quota(), Some(true));
+
+        // Removed keys are rejected
+        auth.set(keys(
+            r#"[{"key_hash": "reader", "name": "reader", "scopes": ["read"]}]"#,
+        ));
+        assert!(matches!(
+            auth.authorize(Some("secret"), Scope::Read),
+            Err(Error::Unauthorized)
+        ));
+    }
+}
@@ -3,7 +3,7 @@ use hyper::{header, header::HeaderValue, Body, Error as HttpError, Response, Sta
 use serde_json::{json, Error as JsonError, Value as JsonValue};
 use thiserror::Error;
 
-use super::CONTENT_JSON;
+use super::{submissions_json, Scope, Submission, SubmissionStatus, CONTENT_JSON};
 use crate::orders;
 
 /// See <https://0x.org/docs/api#error-reporting-format>
@@ -76,6 +76,8 @@ impl From<orders::Error> for ValidationError {
             | orders::Error::Unfunded
             | orders::Error::FullyFilled => Self::InvalidOrder(e),
             orders::Error::InvalidSignature => Self::InvalidSignature,
+            orders::Error::UnsupportedToken => Self::UnsupportedToken,
+            orders::Error::DeniedMaker => Self::AddressNotSupported,
             orders::Error::InvalidVerifyingContract => Self::InternalError,
         }
     }
@@ -96,43 +98,90 @@ pub enum Error {
     InvalidContentType,
     #[error("internal error when validating orders")]
     InternalError,
+    /// Errors of a single order
     #[error("Validation failed")]
     OrderInvalid(Vec<ValidationError>),
+    /// Results of a batch with rejected orders
+    #[error("Validation failed")]
+    BatchRejected(Vec<Submission>),
+    #[error("invalid mode {0:?}, expecting \"best-effort\" or \"atomic\"")]
+    InvalidMode(String),
+    #[error("too many requests")]
+    RateLimited,
+    #[error("request body larger than {0} bytes")]
+    BodyTooLarge(usize),
+    #[error("more than {0} orders in request")]
+    TooManyOrders(usize),
+    #[error("missing or unknown API key")]
+    Unauthorized,
+    #[error("API key lacks the {0:?} scope")]
+    Forbidden(Scope),
 }
 
 impl Error {
     /// Create error response
     /// See <https://0x.org/docs/api#errors>
     pub fn into_response(self) -> Response<Body> {
-        let (code, status_code) = match self {
+        let (code, status_code) = match &self {
             Error::InvalidMethod => (405, StatusCode::METHOD_NOT_ALLOWED),
             Error::NotFound => (404, StatusCode::NOT_FOUND),
             Error::Json(_) => (101, StatusCode::BAD_REQUEST),
-            Error::OrderInvalid(_) => (100, StatusCode::BAD_REQUEST),
+            // Some orders of the batch were added
+            Error::BatchRejected(submissions)
+                if submissions
+                    .iter()
+                    .any(|submission| submission.status == SubmissionStatus::Accepted) =>
+            {
+                (100, StatusCode::MULTI_STATUS)
+            }
+            Error::OrderInvalid(_) | Error::BatchRejected(_) => (100, StatusCode::BAD_REQUEST),
+            Error::RateLimited => (103, StatusCode::TOO_MANY_REQUESTS),
+            // The 0x API's general `InvalidAPIKey` code, not used by SRA itself
+            Error::Unauthorized => (107, StatusCode::UNAUTHORIZED),
+            Error::Forbidden(_) => (107, StatusCode::FORBIDDEN),
+            Error::BodyTooLarge(_) | Error::TooManyOrders(_) => {
+                (100, StatusCode::PAYLOAD_TOO_LARGE)
+            }
             _ => (400, StatusCode::BAD_REQUEST),
         };
-        let validation = if let Error::OrderInvalid(validation) = &self {
-            JsonValue::Array(
-                validation
-                    .iter()
-                    .enumerate()
-                    .map(|(i, e)| e.to_json(i))
-                    .collect(),
-            )
-        } else {
-            json!([])
+        let (validation, results): (Vec<JsonValue>, Option<JsonValue>) = match &self {
+            // All errors are of the one submitted order
+            Error::OrderInvalid(validation) => {
+                (validation.iter().map(|e| e.to_json(0)).collect(), None)
+            }
+            Error::BatchRejected(submissions) => {
+                (
+                    submissions
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(i, submission)| {
+                            submission.errors.iter().map(move |e| e.to_json(i))
+                        })
+                        .collect(),
+                    Some(submissions_json(submissions)),
+                )
+            }
+            _ => (vec![], None),
         };
         let reason = format!("{:?}", AnyError::from(self));
-        let json = json!({
+        let mut json = json!({
             "code": code,
             "reason": reason,
             "validationErrors": validation
         });
+        if let Some(results) = results {
+            json["results"] = results;
+        }
         let json_str = serde_json::to_string_pretty(&json).unwrap_or_default();
         let mut response = Response::new(Body::from(json_str));
         response
             .headers_mut()
             .insert(header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_JSON));
+        if status_code == StatusCode::TOO_MANY_REQUESTS {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+        }
         *response.status_mut() = status_code;
         response
     }
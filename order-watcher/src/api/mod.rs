@@ -2,30 +2,67 @@
 //!
 //! See <https://0x.org/docs/api#post-srav4order>
 //! See <https://0x.org/docs/api#post-srav4orders>
+//!
+//! Additionally `/orders/validate` checks orders without submitting them.
+//!
+//! `/orders` responds with a result per order in request order. Its `mode`
+//! query parameter selects how rejected orders affect the others:
+//!
+//! * `best-effort` (default) Valid orders are added, even if others are
+//!   rejected.
+//! * `atomic` Orders are only added if none are rejected, otherwise the valid
+//!   ones are skipped.
+//!
+//! If any order is rejected the response is an SRA validation error, with
+//! `signedOrder[i]` referring to the rejected orders and the results in
+//! `results`. Its status is 207 if some orders were added, 400 otherwise.
 
+mod auth;
 mod error;
+mod rate_limit;
 
-use core::{convert::Infallible, future::Future};
-use std::{net::SocketAddr, sync::Arc};
+use core::{
+    convert::{Infallible, TryFrom},
+    future::Future,
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::Instant,
+};
 
-use anyhow::{Context as _, Result as AnyResult};
+use anyhow::{anyhow, Context as _, Result as AnyResult};
+use ethabi::Address;
 use hyper::{
-    body::Buf as _,
+    body::HttpBody as _,
     header,
+    header::HeaderValue,
+    server::conn::AddrStream,
     service::{make_service_fn, service_fn},
     Body, Method, Request, Response, Server, StatusCode,
 };
 use once_cell::sync::Lazy;
 use prometheus::{
-    exponential_buckets, register_histogram, register_int_counter, register_int_counter_vec,
-    Histogram, IntCounter, IntCounterVec,
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, Histogram, HistogramVec, IntCounter, IntCounterVec,
 };
-use serde::de::DeserializeOwned;
-use serde_json::{self};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{self, json, Value as JsonValue};
+use structopt::StructOpt;
 use tracing::info;
+use web3::types::H256;
 
-pub use self::error::Error;
-use crate::{orders::SignedOrder, App};
+use self::rate_limit::RateLimiter;
+pub use self::{
+    auth::{Auth, Client, Scope, API_KEY_HEADER},
+    error::{Error, ValidationError},
+};
+use crate::{
+    orders::{SignedOrder, SignedOrderState},
+    App,
+};
 
 const CONTENT_JSON: &str = "application/json";
 
@@ -39,21 +76,314 @@ static ORDERS: Lazy<Histogram> = Lazy::new(|| {
     )
     .unwrap()
 });
+static VALIDATE: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "api_validate",
+        "Number of API /orders/validate requests by number of orders.",
+        exponential_buckets(1.0, 2.0, 10).unwrap()
+    )
+    .unwrap()
+});
+static RATE_LIMITED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "api_rate_limited",
+        "Number of API requests rejected by rate limit.",
+        &["limit"]
+    )
+    .unwrap()
+});
 static STATUS: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
         "api_response_status",
-        "The API responses by status code.",
-        &["status_code"]
+        "The API responses by status code and API key.",
+        &["status_code", "key"]
     )
     .unwrap()
 });
-static LATENCY: Lazy<Histogram> = Lazy::new(|| {
-    register_histogram!("api_latency_seconds", "The API latency in seconds.").unwrap()
+static LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "api_latency_seconds",
+        "The API latency in seconds by API key.",
+        &["key"]
+    )
+    .unwrap()
 });
 
+#[derive(Clone, PartialEq, Debug, StructOpt)]
+pub struct Options {
+    /// Requests per second allowed per client IP
+    #[structopt(long, env = "API_IP_RATE", default_value = "10")]
+    pub api_ip_rate: f64,
+
+    /// Request burst allowed per client IP
+    #[structopt(long, env = "API_IP_BURST", default_value = "20")]
+    pub api_ip_burst: u32,
+
+    /// Orders per second allowed per maker address
+    #[structopt(long, env = "API_MAKER_RATE", default_value = "1")]
+    pub api_maker_rate: f64,
+
+    /// Order burst allowed per maker address. Must be at least the maximum
+    /// number of orders per request.
+    #[structopt(long, env = "API_MAKER_BURST", default_value = "100")]
+    pub api_maker_burst: u32,
+
+    /// Maximum number of orders per request. Each order with a valid
+    /// signature costs an Ethereum call, so this also bounds the calls per
+    /// request.
+    #[structopt(long, env = "API_MAX_ORDERS", default_value = "100")]
+    pub api_max_orders: usize,
+
+    /// Maximum request body size in bytes
+    #[structopt(long, env = "API_MAX_BODY", default_value = "4194304")]
+    pub api_max_body: usize,
+
+    /// Identify clients by the last `X-Forwarded-For` address, for use behind
+    /// a proxy
+    #[structopt(long, env = "API_FORWARDED_FOR")]
+    pub api_forwarded_for: bool,
+
+    #[structopt(flatten)]
+    pub auth: auth::Options,
+}
+
+/// Request limits, shared by the submit and gRPC servers
+pub struct Limits {
+    options: Options,
+    ip:      RateLimiter<IpAddr>,
+    maker:   RateLimiter<Address>,
+}
+
+impl Limits {
+    pub fn new(options: Options) -> AnyResult<Self> {
+        // Requests of a single maker could otherwise never be admitted
+        let max_orders = u32::try_from(options.api_max_orders).unwrap_or(u32::MAX);
+        if options.api_maker_burst < max_orders {
+            return Err(anyhow!(
+                "API maker burst {} is less than the maximum of {} orders per request",
+                options.api_maker_burst,
+                options.api_max_orders
+            ));
+        }
+        Ok(Self {
+            ip: RateLimiter::new(options.api_ip_rate, options.api_ip_burst),
+            maker: RateLimiter::new(options.api_maker_rate, options.api_maker_burst),
+            options,
+        })
+    }
+
+    /// Maximum request body size in bytes
+    pub const fn max_body(&self) -> usize {
+        self.options.api_max_body
+    }
+
+    /// The client address, taken from the `X-Forwarded-For` header if
+    /// configured
+    pub fn client(&self, forwarded_for: Option<&str>, remote: IpAddr) -> IpAddr {
+        if !self.options.api_forwarded_for {
+            return remote;
+        }
+        // The last address is the one added by our proxy, earlier ones can be spoofed
+        forwarded_for
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok())
+            .unwrap_or(remote)
+    }
+
+    /// Check the quota of the API key, or the per-IP rate if it has none
+    pub fn admit(&self, client: &Client, ip: IpAddr) -> Result<(), Error> {
+        match client.check_quota() {
+            Some(true) => Ok(()),
+            Some(false) => {
+                RATE_LIMITED.with_label_values(&["key"]).inc();
+                Err(Error::RateLimited)
+            }
+            None => self.check_ip(ip),
+        }
+    }
+
+    fn check_ip(&self, ip: IpAddr) -> Result<(), Error> {
+        if self.ip.check(ip, 1) {
+            Ok(())
+        } else {
+            RATE_LIMITED.with_label_values(&["ip"]).inc();
+            Err(Error::RateLimited)
+        }
+    }
+
+    /// Check the number of orders in a request
+    pub const fn check_count(&self, count: usize) -> Result<(), Error> {
+        if count > self.options.api_max_orders {
+            return Err(Error::TooManyOrders(self.options.api_max_orders));
+        }
+        Ok(())
+    }
+
+    /// Take one token per order from the bucket of its maker. If any maker
+    /// has too few tokens, none are taken.
+    pub fn charge_makers(&self, makers: impl IntoIterator<Item = Address>) -> Result<(), Error> {
+        let mut counts = HashMap::<Address, u32>::new();
+        for maker in makers {
+            *counts.entry(maker).or_default() += 1;
+        }
+        if self.maker.check_all(counts.into_iter().collect()) {
+            Ok(())
+        } else {
+            RATE_LIMITED.with_label_values(&["maker"]).inc();
+            Err(Error::RateLimited)
+        }
+    }
+}
+
+/// How rejected orders of a batch affect the others, see the module docs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubmitMode {
+    BestEffort,
+    Atomic,
+}
+
+impl FromStr for SubmitMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "best-effort" => Ok(Self::BestEffort),
+            "atomic" => Ok(Self::Atomic),
+            _ => Err(Error::InvalidMode(s.to_owned())),
+        }
+    }
+}
+
+impl SubmitMode {
+    /// The `mode` query parameter, if any
+    fn from_query(query: Option<&str>) -> Result<Self, Error> {
+        query
+            .unwrap_or_default()
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("mode="))
+            .map_or(Ok(Self::BestEffort), Self::from_str)
+    }
+
+    /// In atomic mode, mark the accepted orders as skipped if any order was
+    /// rejected. Returns whether the accepted orders are to be added.
+    pub fn apply(self, submissions: &mut [Submission]) -> bool {
+        let rejected = submissions
+            .iter()
+            .any(|submission| submission.status == SubmissionStatus::Rejected);
+        if !rejected || self == Self::BestEffort {
+            return true;
+        }
+        for submission in submissions {
+            if submission.status == SubmissionStatus::Accepted {
+                submission.status = SubmissionStatus::Skipped;
+            }
+        }
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SubmissionStatus {
+    /// The order was added
+    Accepted,
+    Rejected,
+    /// The order is valid but was not added because others were rejected in
+    /// atomic mode
+    Skipped,
+}
+
+/// Result of submitting an order of a batch
+#[derive(Debug)]
+pub struct Submission {
+    pub hash:   H256,
+    pub status: SubmissionStatus,
+    pub errors: Vec<ValidationError>,
+}
+
+impl Submission {
+    fn to_json(&self, i: usize) -> JsonValue {
+        json!({
+            "orderHash": self.hash,
+            "status": self.status,
+            "validationErrors": self
+                .errors
+                .iter()
+                .map(|e| e.to_json(i))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Result of validating an order without submitting it
+pub struct Validation {
+    /// The order state, unless the order failed validation before fetching it
+    pub state:  Option<SignedOrderState>,
+    pub errors: Vec<ValidationError>,
+}
+
+impl Validation {
+    fn to_json(&self, i: usize) -> JsonValue {
+        json!({
+            "state": self.state,
+            "validationErrors": self
+                .errors
+                .iter()
+                .map(|e| e.to_json(i))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn validations_json(validations: &[Validation]) -> JsonValue {
+    JsonValue::Array(
+        validations
+            .iter()
+            .enumerate()
+            .map(|(i, validation)| validation.to_json(i))
+            .collect(),
+    )
+}
+
+fn submissions_json(submissions: &[Submission]) -> JsonValue {
+    JsonValue::Array(
+        submissions
+            .iter()
+            .enumerate()
+            .map(|(i, submission)| submission.to_json(i))
+            .collect(),
+    )
+}
+
+/// Respond with the results of a batch. Batches with rejected orders are
+/// reported as errors, with status 207 if some orders were added.
+fn submissions_response(submissions: Vec<Submission>) -> Result<Response<Body>, Error> {
+    let accepted = submissions
+        .iter()
+        .all(|submission| submission.status == SubmissionStatus::Accepted);
+    if accepted {
+        Ok(json_response(&submissions_json(&submissions)))
+    } else {
+        Err(Error::BatchRejected(submissions))
+    }
+}
+
+/// Create a successful response with a JSON body
+fn json_response(json: &JsonValue) -> Response<Body> {
+    let mut response = Response::new(Body::from(json.to_string()));
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_JSON));
+    response
+}
+
 /// Parse a [`Request<Body>`] as JSON using Serde and handle using the provided
 /// method.
-async fn json_middleware<F, T, S, U>(request: Request<Body>, mut next: F) -> Result<U, Error>
+async fn json_middleware<F, T, S, U>(
+    request: Request<Body>,
+    max_body: usize,
+    mut next: F,
+) -> Result<U, Error>
 where
     T: DeserializeOwned + Send,
     F: FnMut(T) -> S + Send,
@@ -69,56 +399,142 @@ where
     if !valid_content_type {
         return Err(Error::InvalidContentType);
     }
-    let body = hyper::body::aggregate(request).await?;
-    let value = serde_json::from_reader(body.reader())?;
+    let too_large = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<usize>().ok())
+        .map_or(false, |length| length > max_body);
+    if too_large {
+        return Err(Error::BodyTooLarge(max_body));
+    }
+    let mut body = request.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > max_body {
+            return Err(Error::BodyTooLarge(max_body));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    let value = serde_json::from_slice(&bytes)?;
     next(value).await
 }
 
+fn empty_response() -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::OK;
+    response
+}
+
 /// Route requests based on path
-async fn route(app: Arc<App>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
-    let _timer = LATENCY.start_timer(); // Observes on drop
-
-    let response = match request.uri().path() {
-        "/order" => {
-            json_middleware(request, |req| {
-                ORDER.inc();
-                app.order(req)
-            })
-            .await
-        }
-        "/orders" => {
-            json_middleware(request, |req: Vec<SignedOrder>| {
-                #[allow(clippy::cast_precision_loss)]
-                ORDERS.observe(req.len() as f64);
-                app.orders(req)
-            })
-            .await
+async fn route(
+    app: Arc<App>,
+    limits: Arc<Limits>,
+    auth: Arc<Auth>,
+    remote: IpAddr,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let start = Instant::now();
+
+    let (app, limits) = (&app, &limits);
+    let max_body = limits.max_body();
+    let scope = match request.uri().path() {
+        "/order" | "/orders" => Some(Scope::Submit),
+        "/orders/validate" => Some(Scope::Read),
+        _ => None,
+    };
+    let client = scope.ok_or(Error::NotFound).and_then(|scope| {
+        let key = request
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|key| key.to_str().ok());
+        auth.authorize(key, scope)
+    });
+    let key = client
+        .as_ref()
+        .map_or_else(|_| "none".to_string(), |client| client.name.clone());
+    let forwarded_for = request
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok());
+    let ip = limits.client(forwarded_for, remote);
+    let admitted = client.and_then(|client| limits.admit(&client, ip));
+    let response = match admitted {
+        Err(error) => Err(error),
+        Ok(()) => {
+            match request.uri().path() {
+                "/order" => {
+                    json_middleware(request, max_body, |req: SignedOrder| {
+                        async move {
+                            ORDER.inc();
+                            app.order(req, limits).await
+                        }
+                    })
+                    .await
+                    .map(|()| empty_response())
+                }
+                "/orders" => {
+                    match SubmitMode::from_query(request.uri().query()) {
+                        Err(error) => Err(error),
+                        Ok(mode) => {
+                            json_middleware(request, max_body, |req: Vec<SignedOrder>| {
+                                async move {
+                                    #[allow(clippy::cast_precision_loss)]
+                                    ORDERS.observe(req.len() as f64);
+                                    limits.check_count(req.len())?;
+                                    app.orders(req, mode, limits).await
+                                }
+                            })
+                            .await
+                            .and_then(submissions_response)
+                        }
+                    }
+                }
+                "/orders/validate" => {
+                    json_middleware(request, max_body, |req: Vec<SignedOrder>| {
+                        async move {
+                            #[allow(clippy::cast_precision_loss)]
+                            VALIDATE.observe(req.len() as f64);
+                            limits.check_count(req.len())?;
+                            app.validate_orders(req, limits).await
+                        }
+                    })
+                    .await
+                    .map(|validations| json_response(&validations_json(&validations)))
+                }
+                _ => Err(Error::NotFound),
+            }
         }
-        _ => Err(Error::NotFound),
     }
-    .map_or_else(Error::into_response, |_| {
-        let mut response = Response::new(Body::empty());
-        *response.status_mut() = StatusCode::OK;
-        response
-    });
+    .unwrap_or_else(Error::into_response);
 
     STATUS
-        .with_label_values(&[response.status().as_str()])
+        .with_label_values(&[response.status().as_str(), &key])
         .inc();
+    LATENCY
+        .with_label_values(&[&key])
+        .observe(start.elapsed().as_secs_f64());
     Ok(response)
 }
 
 /// Run a http server on [`socket_address`]
-pub(super) async fn serve(app: App, socket_address: &SocketAddr) -> AnyResult<()> {
+pub(super) async fn serve(
+    app: App,
+    socket_address: &SocketAddr,
+    limits: Arc<Limits>,
+    auth: Arc<Auth>,
+) -> AnyResult<()> {
     // Wrap app in an Arc to make cloning cheaper
     let app = Arc::new(app);
 
-    let service = make_service_fn(move |_connection| {
+    let service = make_service_fn(move |connection: &AddrStream| {
         let app = app.clone();
+        let limits = limits.clone();
+        let auth = auth.clone();
+        let remote = connection.remote_addr().ip();
         async move {
             Ok::<_, Infallible>(service_fn(move |request| {
-                let app = app.clone();
-                route(app, request)
+                route(app.clone(), limits.clone(), auth.clone(), remote, request)
             }))
         }
     });
@@ -139,3 +555,246 @@ pub(super) async fn serve(app: App, socket_address: &SocketAddr) -> AnyResult<()
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::iter;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::orders::OrderStatus;
+
+    #[test]
+    fn test_validations_json() {
+        let state = |status| {
+            SignedOrderState {
+                hash: H256::repeat_byte(0x11),
+                status,
+                taker_asset_filled_amount: 1.into(),
+                taker_asset_fillable_amount: 2.into(),
+                is_signature_valid: true,
+            }
+        };
+        let expired = state(OrderStatus::Expired);
+        let validations = [
+            Validation {
+                state:  Some(state(OrderStatus::Fillable)),
+                errors: vec![],
+            },
+            // Rejected before fetching the state
+            Validation {
+                state:  None,
+                errors: vec![ValidationError::OutOfRange],
+            },
+            // Rejected by its state
+            Validation {
+                state:  Some(expired),
+                errors: vec![expired.validate().unwrap_err().into()],
+            },
+        ];
+        let hash = format!("{:?}", H256::repeat_byte(0x11));
+        assert_eq!(
+            validations_json(&validations),
+            json!([
+                {
+                    "state": {
+                        "orderHash": hash,
+                        "status": "FILLABLE",
+                        "takerAssetFilledAmount": "1",
+                        "takerAssetFillableAmount": "2",
+                        "isSignatureValid": true,
+                    },
+                    "validationErrors": [],
+                },
+                {
+                    "state": null,
+                    "validationErrors": [{
+                        "code": 1004,
+                        "reason": "Value out of range",
+                        "field": "signedOrder[1]",
+                    }],
+                },
+                {
+                    "state": {
+                        "orderHash": hash,
+                        "status": "EXPIRED",
+                        "takerAssetFilledAmount": "1",
+                        "takerAssetFillableAmount": "2",
+                        "isSignatureValid": true,
+                    },
+                    "validationErrors": [{
+                        "code": 1007,
+                        "reason": "ORDER_EXPIRED: order expired according to latest block timestamp",
+                        "field": "signedOrder[2]",
+                    }],
+                },
+            ])
+        );
+    }
+
+    fn limits(args: &[&str]) -> AnyResult<Limits> {
+        Limits::new(Options::from_iter_safe(iter::once(&"").chain(args))?)
+    }
+
+    #[test]
+    fn test_maker_burst() {
+        assert!(limits(&[]).is_ok());
+        assert!(limits(&["--api-maker-burst", "10", "--api-max-orders", "20"]).is_err());
+        assert!(limits(&["--api-maker-burst", "20", "--api-max-orders", "20"]).is_ok());
+    }
+
+    #[test]
+    fn test_charge_makers() {
+        let limits = limits(&["--api-maker-burst", "2", "--api-max-orders", "2"]).unwrap();
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        limits.charge_makers(vec![a, a]).unwrap();
+        assert!(matches!(
+            limits.charge_makers(vec![b, a]),
+            Err(Error::RateLimited)
+        ));
+        // The failed request took no tokens from the other maker
+        limits.charge_makers(vec![b, b]).unwrap();
+    }
+
+    #[test]
+    fn test_rate_limited_response() {
+        let limits = limits(&["--api-ip-rate", "0.001", "--api-ip-burst", "1"]).unwrap();
+        let auth = Auth::new(false, vec![]);
+        let client = auth.authorize(None, Scope::Submit).unwrap();
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        limits.admit(&client, ip).unwrap();
+        let response = limits.admit(&client, ip).unwrap_err().into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        // Other clients are not affected
+        limits.admit(&client, IpAddr::from([127, 0, 0, 2])).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_body_limit() {
+        let request = |length: Option<usize>| {
+            let mut builder = Request::post("/orders").header(header::CONTENT_TYPE, CONTENT_JSON);
+            if let Some(length) = length {
+                builder = builder.header(header::CONTENT_LENGTH, length);
+            }
+            builder.body(Body::from("[1, 2, 3]")).unwrap()
+        };
+        let submit =
+            |request, max_body| json_middleware(request, max_body, |_: JsonValue| async { Ok(()) });
+        submit(request(Some(9)), 9).await.unwrap();
+        submit(request(None), 9).await.unwrap();
+        for length in [Some(9), None] {
+            let error = submit(request(length), 8).await.unwrap_err();
+            assert!(matches!(error, Error::BodyTooLarge(8)));
+            assert_eq!(
+                error.into_response().status(),
+                StatusCode::PAYLOAD_TOO_LARGE
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auth_error_codes() {
+        for (error, status, code) in [
+            (Error::Unauthorized, StatusCode::UNAUTHORIZED, 107),
+            (Error::Forbidden(Scope::Submit), StatusCode::FORBIDDEN, 107),
+        ] {
+            let response = error.into_response();
+            assert_eq!(response.status(), status);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let json: JsonValue = serde_json::from_slice(&body).unwrap();
+            assert_eq!(json["code"], code);
+        }
+    }
+
+    #[test]
+    fn test_submit_mode() {
+        assert_eq!(
+            SubmitMode::from_query(None).unwrap(),
+            SubmitMode::BestEffort
+        );
+        assert_eq!(
+            SubmitMode::from_query(Some("mode=atomic")).unwrap(),
+            SubmitMode::Atomic
+        );
+        assert_eq!(
+            SubmitMode::from_query(Some("page=1&mode=best-effort")).unwrap(),
+            SubmitMode::BestEffort
+        );
+        assert_eq!(
+            SubmitMode::from_query(Some("page=1")).unwrap(),
+            SubmitMode::BestEffort
+        );
+        assert!(matches!(
+            SubmitMode::from_query(Some("mode=all")),
+            Err(Error::InvalidMode(mode)) if mode == "all"
+        ));
+    }
+
+    fn submissions(statuses: &[SubmissionStatus]) -> Vec<Submission> {
+        statuses
+            .iter()
+            .enumerate()
+            .map(|(i, &status)| {
+                Submission {
+                    hash: H256::from_low_u64_be(i as u64),
+                    status,
+                    errors: if status == SubmissionStatus::Rejected {
+                        vec![ValidationError::OutOfRange]
+                    } else {
+                        vec![]
+                    },
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_submissions_json() {
+        use SubmissionStatus::{Accepted, Rejected};
+        let json = submissions_json(&submissions(&[Accepted, Rejected]));
+        assert_eq!(json[0]["status"], "ACCEPTED");
+        assert_eq!(json[0]["validationErrors"], json!([]));
+        assert_eq!(json[1]["orderHash"], json!(H256::from_low_u64_be(1)));
+        assert_eq!(json[1]["status"], "REJECTED");
+        // Errors refer to the index of the order in the request
+        assert_eq!(json[1]["validationErrors"][0]["field"], "signedOrder[1]");
+    }
+
+    #[test]
+    fn test_atomic_mode() {
+        use SubmissionStatus::{Accepted, Rejected, Skipped};
+        let statuses = |submissions: &[Submission]| {
+            submissions
+                .iter()
+                .map(|submission| submission.status)
+                .collect::<Vec<_>>()
+        };
+
+        let mut batch = submissions(&[Accepted, Rejected, Accepted]);
+        assert!(SubmitMode::BestEffort.apply(&mut batch));
+        assert_eq!(statuses(&batch), vec![Accepted, Rejected, Accepted]);
+
+        assert!(!SubmitMode::Atomic.apply(&mut batch));
+        assert_eq!(statuses(&batch), vec![Skipped, Rejected, Skipped]);
+
+        let mut batch = submissions(&[Accepted, Accepted]);
+        assert!(SubmitMode::Atomic.apply(&mut batch));
+        assert_eq!(statuses(&batch), vec![Accepted, Accepted]);
+    }
+
+    #[test]
+    fn test_submissions_response() {
+        use SubmissionStatus::{Accepted, Rejected, Skipped};
+        let status = |statuses: &[SubmissionStatus]| {
+            submissions_response(submissions(statuses))
+                .unwrap_or_else(Error::into_response)
+                .status()
+        };
+        assert_eq!(status(&[Accepted, Accepted]), StatusCode::OK);
+        assert_eq!(status(&[Accepted, Rejected]), StatusCode::MULTI_STATUS);
+        assert_eq!(status(&[Rejected, Rejected]), StatusCode::BAD_REQUEST);
+        assert_eq!(status(&[Skipped, Rejected]), StatusCode::BAD_REQUEST);
+    }
+}
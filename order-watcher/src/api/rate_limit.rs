@@ -0,0 +1,112 @@
+use std::{collections::HashMap, hash::Hash, sync::Mutex, time::Instant};
+
+/// Number of keys above which full buckets are dropped
+const MAX_KEYS: usize = 10_000;
+
+/// Token buckets per key, refilled at `rate` tokens per second up to `burst`.
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    rate:    f64,
+    burst:   f64,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens:  f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = elapsed.mul_add(rate, self.tokens).min(burst);
+        self.updated = now;
+    }
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            rate,
+            burst: burst.into(),
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Take `tokens` from the bucket of `key`, returns `false` if there are
+    /// not enough.
+    pub fn check(&self, key: K, tokens: u32) -> bool {
+        self.check_at(vec![(key, tokens)], Instant::now())
+    }
+
+    /// Take tokens from the buckets of several distinct keys. Either all
+    /// buckets have enough tokens and are charged, or none are and `false` is
+    /// returned.
+    pub fn check_all(&self, charges: Vec<(K, u32)>) -> bool {
+        self.check_at(charges, Instant::now())
+    }
+
+    fn check_at(&self, charges: Vec<(K, u32)>, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_KEYS {
+            // Full buckets are the same as missing ones
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, bucket| {
+                bucket.refill(now, rate, burst);
+                bucket.tokens < burst
+            });
+        }
+        let enough = charges.iter().all(|(key, tokens)| {
+            let available = buckets.get_mut(key).map_or(self.burst, |bucket| {
+                bucket.refill(now, self.rate, self.burst);
+                bucket.tokens
+            });
+            available >= f64::from(*tokens)
+        });
+        if !enough {
+            return false;
+        }
+        for (key, tokens) in charges {
+            let bucket = buckets.entry(key).or_insert(Bucket {
+                tokens:  self.burst,
+                updated: now,
+            });
+            bucket.tokens -= f64::from(tokens);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(2.0, 4);
+        let start = Instant::now();
+        assert!(limiter.check_at(vec![(1, 3)], start));
+        assert!(!limiter.check_at(vec![(1, 2)], start));
+        assert!(limiter.check_at(vec![(2, 4)], start));
+        assert!(limiter.check_at(vec![(1, 2)], start + Duration::from_millis(500)));
+        assert!(!limiter.check_at(vec![(1, 1)], start + Duration::from_millis(500)));
+        // Refills up to the burst size
+        assert!(!limiter.check_at(vec![(1, 5)], start + Duration::from_secs(60)));
+        assert!(limiter.check_at(vec![(1, 4)], start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_check_all() {
+        let limiter = RateLimiter::new(2.0, 4);
+        let start = Instant::now();
+        assert!(limiter.check_at(vec![(1, 3)], start));
+
+        // Nothing is taken if any bucket runs out
+        assert!(!limiter.check_at(vec![(2, 4), (1, 2)], start));
+        assert!(limiter.check_at(vec![(2, 4), (1, 1)], start));
+        assert!(!limiter.check_at(vec![(1, 1)], start));
+    }
+}
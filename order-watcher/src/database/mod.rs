@@ -3,6 +3,7 @@ mod schema;
 
 use core::fmt::Debug;
 use std::{
+    collections::{HashMap, HashSet},
     convert::TryFrom,
     sync::{Arc, Mutex},
 };
@@ -12,20 +13,24 @@ use diesel::{
     debug_query, delete, insert_into,
     pg::{Pg, PgConnection},
     prelude::*,
+    sql_query,
+    sql_types::Text,
     update,
 };
 use once_cell::sync::Lazy;
 use prometheus::{
-    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter_vec,
-    register_int_gauge, Histogram, HistogramVec, IntCounterVec, IntGauge,
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, Histogram, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge,
 };
 use structopt::StructOpt;
 use tokio::task::spawn_blocking;
 use tracing::{info, trace};
 use url::Url;
-use web3::types::{H256, U128, U256, U64};
+use web3::types::{Address, H256, U128, U256, U64};
 
-pub use self::schema::signed_orders_v4;
+use self::queryable::parse_prefixed_hash;
+pub use self::schema::{api_keys, signed_orders_v4};
 use crate::{
     ethereum::ChainInfo,
     orders::Signature,
@@ -40,6 +45,20 @@ static LATENCY: Lazy<Histogram> =
     Lazy::new(|| register_histogram!("db_latency_seconds", "The DB latency in seconds.").unwrap());
 static ORDERS: Lazy<IntGauge> =
     Lazy::new(|| register_int_gauge!("db_orders", "Number of orders in the database.").unwrap());
+static INSERTED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "db_orders_inserted",
+        "Number of orders inserted into the database."
+    )
+    .unwrap()
+});
+static DELETED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "db_orders_deleted",
+        "Number of orders deleted from the database."
+    )
+    .unwrap()
+});
 static STEP_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
     register_histogram_vec!(
         "db_get_orders_step_duration",
@@ -64,6 +83,39 @@ pub struct Options {
     pub database: Url,
 }
 
+/// Order query, unset fields match any order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct OrderFilter {
+    pub maker:       Option<Address>,
+    pub maker_token: Option<Address>,
+    pub taker_token: Option<Address>,
+}
+
+impl OrderFilter {
+    pub fn matches(&self, order: &SignedOrderWithMetadata) -> bool {
+        let order = &order.signed_order.order;
+        self.maker.map_or(true, |maker| maker == order.maker)
+            && self
+                .maker_token
+                .map_or(true, |maker_token| maker_token == order.maker_token)
+            && self
+                .taker_token
+                .map_or(true, |taker_token| taker_token == order.taker_token)
+    }
+}
+
+/// A row of the `api_keys` table
+#[derive(Clone, PartialEq, Debug, Queryable)]
+pub struct ApiKeyRecord {
+    /// Hex encoded SHA-256 hash of the key
+    pub key_hash: String,
+    pub name:     String,
+    /// Comma separated list of `submit`, `read` and `stream`
+    pub scopes:   String,
+    pub rate:     Option<f64>,
+    pub burst:    Option<i32>,
+}
+
 #[derive(Clone)]
 pub struct Database {
     url:        Url,
@@ -164,6 +216,84 @@ impl Database {
         Ok(signed_orders_with_metadatas)
     }
 
+    /// Fetch a stored order by hash, valid or not.
+    pub async fn get_order(&self, order_hash: H256) -> AnyResult<Option<SignedOrderWithMetadata>> {
+        OPS_COUNTER.with_label_values(&["get_order"]).inc();
+        let mut order = self
+            .with_connection(move |connection| {
+                use signed_orders_v4::{hash, table};
+                table
+                    .filter(hash.eq(format!("{:?}", order_hash)))
+                    .first::<SignedOrderWithMetadata>(connection)
+                    .optional()
+                    .any()
+            })
+            .await
+            .context("error in get_order query")?;
+        if let Some(order) = &mut order {
+            order.signed_order.order.chain_id = self.chain_id.as_u64();
+        }
+        Ok(order)
+    }
+
+    /// Fetch the valid orders matching `filter`.
+    pub async fn query_orders(
+        &self,
+        filter: OrderFilter,
+    ) -> AnyResult<Vec<SignedOrderWithMetadata>> {
+        OPS_COUNTER.with_label_values(&["query_orders"]).inc();
+        let chain_id = self.chain_id.as_u64();
+        let mut orders = self
+            .with_connection(move |connection| {
+                use signed_orders_v4::{invalid_since, maker, maker_token, table, taker_token};
+                let mut query = table.filter(invalid_since.is_null()).into_boxed();
+                if let Some(address) = filter.maker {
+                    query = query.filter(maker.eq(format!("{:?}", address)));
+                }
+                if let Some(address) = filter.maker_token {
+                    query = query.filter(maker_token.eq(format!("{:?}", address)));
+                }
+                if let Some(address) = filter.taker_token {
+                    query = query.filter(taker_token.eq(format!("{:?}", address)));
+                }
+                trace!(query = %debug_query::<Pg, _>(&query), "query_orders query");
+                query.load::<SignedOrderWithMetadata>(connection).any()
+            })
+            .await
+            .context("error in query_orders query")?;
+        for order in &mut orders {
+            order.signed_order.order.chain_id = chain_id;
+        }
+        Ok(orders)
+    }
+
+    /// Count the valid orders of `maker`.
+    pub async fn count_orders(&self, maker: Address) -> AnyResult<u64> {
+        OPS_COUNTER.with_label_values(&["count_orders"]).inc();
+        let count = self
+            .with_connection(move |connection| {
+                use signed_orders_v4::{invalid_since, table};
+                table
+                    .filter(invalid_since.is_null())
+                    .filter(signed_orders_v4::maker.eq(format!("{:?}", maker)))
+                    .count()
+                    .get_result::<i64>(connection)
+                    .any()
+            })
+            .await
+            .context("error in count_orders query")?;
+        Ok(u64::try_from(count)?)
+    }
+
+    pub async fn get_api_keys(&self) -> AnyResult<Vec<ApiKeyRecord>> {
+        OPS_COUNTER.with_label_values(&["get_api_keys"]).inc();
+        self.with_connection(move |connection| {
+            api_keys::table.load::<ApiKeyRecord>(connection).any()
+        })
+        .await
+        .context("error in get_api_keys query")
+    }
+
     #[allow(clippy::large_types_passed_by_value)]
     pub async fn insert_order(
         &self,
@@ -172,56 +302,73 @@ impl Database {
         OPS_COUNTER.with_label_values(&["insert_order"]).inc();
         trace!(order_hash = ?signed_order_with_metadata.metadata.hash, "Inserting order in database");
         // TODO: Validate order
-        self.with_connection(move |connection| {
-            use signed_orders_v4::{
-                created_at, expiry, fee_recipient, hash, maker, maker_amount, maker_token, pool,
-                remaining_fillable_taker_amount, salt, sender, signature, taker, taker_amount,
-                taker_token, taker_token_fee_amount, verifying_contract,
-            };
+        self.with_connection(move |connection| insert(connection, signed_order_with_metadata))
+            .await
+            .context("error in insert_order query")?;
+        INSERTED.inc();
+        Ok(())
+    }
 
-            let signed_order = signed_order_with_metadata.signed_order;
-            let order = signed_order.order;
-            let metadata = signed_order_with_metadata.metadata;
-
-            let query = insert_into(signed_orders_v4::table)
-                .values((
-                    hash.eq(format!("{:?}", metadata.hash)),
-                    maker_token.eq(format!("{:?}", order.maker_token)),
-                    taker_token.eq(format!("{:?}", order.taker_token)),
-                    maker_amount.eq(format!("{:?}", order.maker_amount)),
-                    taker_amount.eq(format!("{:?}", order.taker_amount)),
-                    maker.eq(format!("{:?}", order.maker)),
-                    taker.eq(format!("{:?}", order.taker)),
-                    pool.eq(format!("{:?}", order.pool)),
-                    expiry.eq(format!("{:?}", order.expiry)),
-                    salt.eq(format!("{:?}", order.salt)),
-                    verifying_contract.eq(format!("{:?}", order.verifying_contract)),
-                    taker_token_fee_amount.eq(format!("{:?}", order.taker_token_fee_amount)),
-                    sender.eq(format!("{:?}", order.sender)),
-                    fee_recipient.eq(format!("{:?}", order.fee_recipient)),
-                    signature.eq(concatenate(&signed_order.signature)),
-                    remaining_fillable_taker_amount.eq(format!("{:?}", metadata.remaining)),
-                    created_at.eq(metadata.created_at),
-                ))
-                .on_conflict(hash)
-                .do_update()
-                .set(remaining_fillable_taker_amount.eq(format!("{:?}", metadata.remaining)));
-            trace!(query = %debug_query::<Pg, _>(&query), "insert_order query");
-            query.execute(connection)?;
-            Ok(())
-        })
-        .await
-        .context("error in insert_order query")
+    /// Insert orders in a single transaction, so either all or none are
+    /// stored.
+    ///
+    /// With `max_per_maker`, the valid orders of each maker are counted in the
+    /// transaction, holding a lock per maker until it ends. If the orders
+    /// would take a maker over the limit, none are stored and the hashes of
+    /// the orders over the limit are returned.
+    pub async fn insert_orders(
+        &self,
+        orders: Vec<SignedOrderWithMetadata>,
+        max_per_maker: Option<u64>,
+    ) -> AnyResult<Vec<H256>> {
+        OPS_COUNTER.with_label_values(&["insert_orders"]).inc();
+        trace!(orders = orders.len(), "Inserting orders in database");
+        let count = orders.len() as u64;
+        let over_limit = self
+            .with_connection(move |connection| {
+                connection.transaction(|| {
+                    if let Some(max) = max_per_maker {
+                        let over_limit = over_limit(connection, &orders, max)?;
+                        if !over_limit.is_empty() {
+                            return Ok(over_limit);
+                        }
+                    }
+                    orders
+                        .into_iter()
+                        .try_for_each(|order| insert(connection, order))?;
+                    Ok(vec![])
+                })
+            })
+            .await
+            .context("error in insert_orders query")?;
+        if over_limit.is_empty() {
+            INSERTED.inc_by(count);
+        }
+        Ok(over_limit)
     }
 
-    pub async fn update_order(&self, order_hash: H256, remaining: U128) -> AnyResult<()> {
+    pub async fn update_order(
+        &self,
+        order_hash: H256,
+        remaining: U128,
+        filled: U128,
+    ) -> AnyResult<()> {
         OPS_COUNTER.with_label_values(&["update_order"]).inc();
-        trace!(?order_hash, ?remaining, "Updating order in database");
+        trace!(
+            ?order_hash,
+            ?remaining,
+            ?filled,
+            "Updating order in database"
+        );
         self.with_connection(move |connection| {
-            use signed_orders_v4::{hash, invalid_since, remaining_fillable_taker_amount, table};
+            use signed_orders_v4::{
+                hash, invalid_since, remaining_fillable_taker_amount, table,
+                taker_asset_filled_amount,
+            };
 
             let query = update(table.filter(hash.eq(format!("{:?}", order_hash)))).set((
                 remaining_fillable_taker_amount.eq(remaining.to_string()),
+                taker_asset_filled_amount.eq(filled.to_string()),
                 invalid_since.eq(Option::<i64>::None),
             ));
             trace!(query = %debug_query::<Pg, _>(&query), "update_order query");
@@ -259,23 +406,63 @@ impl Database {
         .context("error in invalidate_order query")
     }
 
-    pub async fn delete_orders(&self, block_number: U64) -> AnyResult<()> {
+    /// Orders invalid since `block_number` or before, which can be deleted
+    /// with [`Self::delete_orders`].
+    pub async fn deletable_orders(
+        &self,
+        block_number: U64,
+    ) -> AnyResult<Vec<SignedOrderWithMetadata>> {
+        OPS_COUNTER.with_label_values(&["deletable_orders"]).inc();
+        let chain_id = self.chain_id.as_u64();
+        let mut orders = self
+            .with_connection(move |connection| {
+                use signed_orders_v4::{invalid_since, table};
+                let signed_block_number = i64::try_from(block_number).unwrap();
+                let query = table.filter(invalid_since.le(signed_block_number));
+                trace!(query = %debug_query::<Pg, _>(&query), "deletable_orders query");
+                query.load::<SignedOrderWithMetadata>(connection).any()
+            })
+            .await
+            .context("error in deletable_orders query")?;
+        for order in &mut orders {
+            order.signed_order.order.chain_id = chain_id;
+        }
+        Ok(orders)
+    }
+
+    /// Delete the invalid orders with `order_hashes`, returning the hashes of
+    /// the deleted orders. Orders that have become valid again are kept.
+    pub async fn delete_orders(&self, order_hashes: Vec<H256>) -> AnyResult<Vec<H256>> {
         OPS_COUNTER.with_label_values(&["delete_orders"]).inc();
         trace!(
-            ?block_number,
-            "Deleting orders invalid since block (or before) from database"
+            orders = order_hashes.len(),
+            "Deleting invalid orders from database"
         );
-        self.with_connection(move |connection| {
-            use signed_orders_v4::{invalid_since, table};
-            let signed_block_number = i64::try_from(block_number).unwrap();
-            let query = delete(table.filter(invalid_since.le(signed_block_number)));
-            trace!(query = %debug_query::<Pg, _>(&query), "delete_orders query");
-            let count_deleted = query.execute(connection)?;
-            info!("{} invalid order(s) deleted", count_deleted);
-            Ok(())
-        })
-        .await
-        .context("error in delete_orders query")
+        let deleted = self
+            .with_connection(move |connection| {
+                use signed_orders_v4::{hash, invalid_since, table};
+                let hashes = order_hashes
+                    .iter()
+                    .map(|order_hash| format!("{:?}", order_hash))
+                    .collect::<Vec<_>>();
+                let query = delete(
+                    table
+                        .filter(hash.eq_any(hashes))
+                        .filter(invalid_since.is_not_null()),
+                )
+                .returning(hash);
+                trace!(query = %debug_query::<Pg, _>(&query), "delete_orders query");
+                let deleted = query.get_results::<String>(connection)?;
+                info!("{} invalid order(s) deleted", deleted.len());
+                Ok(deleted)
+            })
+            .await
+            .context("error in delete_orders query")?;
+        DELETED.inc_by(deleted.len() as u64);
+        Ok(deleted
+            .iter()
+            .map(|hash| parse_prefixed_hash(hash))
+            .collect())
     }
 
     /// Execute a blocking operation using the [`PgConnection`] asynchronously
@@ -298,6 +485,117 @@ impl Database {
     }
 }
 
+#[allow(clippy::large_types_passed_by_value)]
+/// Lock the makers of `orders` for the current transaction and return the
+/// hashes of the orders that would take them over `max` valid orders.
+fn over_limit(
+    connection: &PgConnection,
+    orders: &[SignedOrderWithMetadata],
+    max: u64,
+) -> AnyResult<Vec<H256>> {
+    use signed_orders_v4::{hash, invalid_since, maker, table};
+
+    let orders = orders
+        .iter()
+        .map(|order| (order.metadata.hash, order.signed_order.order.maker))
+        .collect::<Vec<_>>();
+    let hashes = orders
+        .iter()
+        .map(|(order_hash, _)| format!("{:?}", order_hash))
+        .collect::<Vec<_>>();
+    let mut makers = orders
+        .iter()
+        .map(|(_, address)| *address)
+        .collect::<Vec<_>>();
+    // Lock in a consistent order to avoid deadlocks between batches
+    makers.sort_unstable();
+    makers.dedup();
+    let mut valid = HashMap::new();
+    for address in makers {
+        let address_str = format!("{:?}", address);
+        sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind::<Text, _>(&address_str)
+            .execute(connection)?;
+        // Orders of the batch are counted below, even if already stored
+        let count = table
+            .filter(invalid_since.is_null())
+            .filter(maker.eq(address_str))
+            .filter(hash.ne_all(&hashes))
+            .count()
+            .get_result::<i64>(connection)?;
+        valid.insert(address, u64::try_from(count)?);
+    }
+    Ok(orders_over_limit(&orders, &valid, max))
+}
+
+/// The hashes of the orders, given as hash and maker, that would take their
+/// maker over `max` valid orders, given the number of `valid` orders they
+/// already have.
+fn orders_over_limit(
+    orders: &[(H256, Address)],
+    valid: &HashMap<Address, u64>,
+    max: u64,
+) -> Vec<H256> {
+    let mut pending = HashMap::<Address, u64>::new();
+    let mut seen = HashSet::new();
+    orders
+        .iter()
+        .filter(|(hash, _)| seen.insert(*hash))
+        .filter_map(|(hash, maker)| {
+            let count = pending.entry(*maker).or_default();
+            *count += 1;
+            let total = valid.get(maker).copied().unwrap_or_default() + *count;
+            (total > max).then(|| *hash)
+        })
+        .collect()
+}
+
+fn insert(
+    connection: &PgConnection,
+    signed_order_with_metadata: SignedOrderWithMetadata,
+) -> AnyResult<()> {
+    use signed_orders_v4::{
+        created_at, expiry, fee_recipient, hash, maker, maker_amount, maker_token, pool,
+        remaining_fillable_taker_amount, salt, sender, signature, taker, taker_amount,
+        taker_asset_filled_amount, taker_token, taker_token_fee_amount, verifying_contract,
+    };
+
+    let signed_order = signed_order_with_metadata.signed_order;
+    let order = signed_order.order;
+    let metadata = signed_order_with_metadata.metadata;
+
+    let query = insert_into(signed_orders_v4::table)
+        .values((
+            hash.eq(format!("{:?}", metadata.hash)),
+            maker_token.eq(format!("{:?}", order.maker_token)),
+            taker_token.eq(format!("{:?}", order.taker_token)),
+            maker_amount.eq(format!("{:?}", order.maker_amount)),
+            taker_amount.eq(format!("{:?}", order.taker_amount)),
+            maker.eq(format!("{:?}", order.maker)),
+            taker.eq(format!("{:?}", order.taker)),
+            pool.eq(format!("{:?}", order.pool)),
+            expiry.eq(format!("{:?}", order.expiry)),
+            salt.eq(format!("{:?}", order.salt)),
+            verifying_contract.eq(format!("{:?}", order.verifying_contract)),
+            taker_token_fee_amount.eq(format!("{:?}", order.taker_token_fee_amount)),
+            sender.eq(format!("{:?}", order.sender)),
+            fee_recipient.eq(format!("{:?}", order.fee_recipient)),
+            signature.eq(concatenate(&signed_order.signature)),
+            remaining_fillable_taker_amount.eq(format!("{:?}", metadata.remaining)),
+            taker_asset_filled_amount.eq(format!("{:?}", metadata.filled)),
+            created_at.eq(metadata.created_at),
+        ))
+        .on_conflict(hash)
+        .do_update()
+        .set((
+            remaining_fillable_taker_amount.eq(format!("{:?}", metadata.remaining)),
+            taker_asset_filled_amount.eq(format!("{:?}", metadata.filled)),
+        ));
+    trace!(query = %debug_query::<Pg, _>(&query), "insert_order query");
+    query.execute(connection)?;
+    Ok(())
+}
+
 fn concatenate(signature: &Signature) -> String {
     vec![
         u32::from(signature.signature_type).to_string(),
@@ -312,6 +610,28 @@ fn concatenate(signature: &Signature) -> String {
 pub mod test {
     use super::*;
 
+    #[test]
+    fn test_orders_over_limit() {
+        let (a, b) = (Address::repeat_byte(0xa), Address::repeat_byte(0xb));
+        let hash = H256::from_low_u64_be;
+        let orders = [
+            (hash(1), a),
+            (hash(2), b),
+            (hash(3), a),
+            // Duplicates are stored once
+            (hash(3), a),
+            (hash(4), a),
+        ];
+        let valid = vec![(a, 1)].into_iter().collect();
+        assert_eq!(orders_over_limit(&orders, &valid, 3), vec![hash(4)]);
+        assert_eq!(orders_over_limit(&orders, &valid, 1), vec![
+            hash(1),
+            hash(3),
+            hash(4)
+        ]);
+        assert_eq!(orders_over_limit(&orders, &HashMap::new(), 3), vec![]);
+    }
+
     #[tokio::test]
     #[ignore]
     #[allow(clippy::semicolon_if_nothing_returned)] // False positive
@@ -328,12 +648,29 @@ pub mod test {
         db.invalidate_order(signed_order.order.hash(), 10.into())
             .await
             .unwrap();
-        db.delete_orders(10.into()).await.unwrap();
+        let deletable = db.deletable_orders(10.into()).await.unwrap();
+        let hashes = deletable.iter().map(|order| order.metadata.hash).collect();
+        let deleted = db.delete_orders(hashes).await.unwrap();
+        assert_eq!(deleted, vec![signed_order.order.hash()]);
 
         db.insert_order(signed_orders_with_metadata[0])
             .await
             .unwrap();
 
+        // Orders over the maker limit are returned and none are stored
+        let maker = signed_order.order.maker;
+        let open = db.count_orders(maker).await.unwrap();
+        let over_limit = db
+            .insert_orders(vec![signed_orders_with_metadata[0]], Some(open - 1))
+            .await
+            .unwrap();
+        assert_eq!(over_limit, vec![signed_order.order.hash()]);
+        let over_limit = db
+            .insert_orders(vec![signed_orders_with_metadata[0]], Some(open))
+            .await
+            .unwrap();
+        assert_eq!(over_limit, vec![]);
+
         // let new_order: Vec<SignedOrder> = table
         //     .filter(hash.eq(format!("{:?}", order.order.hash())))
         //     .load(&conn)
@@ -36,6 +36,7 @@ impl Queryable<signed_orders_v4::SqlType, diesel::pg::Pg> for SignedOrderWithMet
         String,
         DateTime<Utc>,
         Option<i64>,
+        String,
     );
 
     #[allow(clippy::similar_names)] // `maker` and `taker` are too similar.
@@ -59,6 +60,7 @@ impl Queryable<signed_orders_v4::SqlType, diesel::pg::Pg> for SignedOrderWithMet
             remaining_fillable_taker_amount,
             created_at,
             invalid_since,
+            taker_asset_filled_amount,
         ) = row;
         let order = LimitOrder {
             maker:                  parse_prefixed_address(&maker),
@@ -100,6 +102,7 @@ impl Queryable<signed_orders_v4::SqlType, diesel::pg::Pg> for SignedOrderWithMet
         let metadata = Metadata {
             hash: parse_prefixed_hash(&hash),
             remaining: parse_u128(&remaining_fillable_taker_amount),
+            filled: parse_u128(&taker_asset_filled_amount),
             status: if invalid_since.is_none() {
                 OrderStatus::Fillable
             } else {
@@ -122,7 +125,7 @@ fn parse_prefixed_address(s: &str) -> Address {
     }
 }
 
-fn parse_prefixed_hash(s: &str) -> H256 {
+pub(super) fn parse_prefixed_hash(s: &str) -> H256 {
     H256::from_str(&s[2..]).unwrap_or_else(|_| panic!("invalid hex string for H256: {:?}", s))
 }
 
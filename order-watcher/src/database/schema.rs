@@ -20,5 +20,16 @@ table! {
         remaining_fillable_taker_amount -> Varchar,
         created_at -> Timestamptz,
         invalid_since -> Nullable<BigInt>,
+        taker_asset_filled_amount -> Varchar,
+    }
+}
+
+table! {
+    api_keys (key_hash) {
+        key_hash -> Varchar,
+        name -> Varchar,
+        scopes -> Varchar,
+        rate -> Nullable<Double>,
+        burst -> Nullable<Integer>,
     }
 }
@@ -0,0 +1,328 @@
+//! Implements the gRPC order service, see `order_service.proto`
+
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use anyhow::{Context as _, Result as AnyResult};
+use prost::Message as _;
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{transport::Server, Request, Response, Status};
+use tracing::{error, info};
+use types::{
+    proto::{
+        zeroex::{
+            order_submission::Status as SubmissionStatusProto,
+            signature::Type as SignatureTypeProto, GetOrderRequest, OrderEvent as OrderEventProto,
+            OrderFilter as OrderFilterProto, OrderSubmission as OrderSubmissionProto,
+            QueryOrdersResponse, SignedOrder as SignedOrderProto, SubmitOrdersRequest,
+            SubmitOrdersResponse, ValidationError as ValidationErrorProto,
+        },
+        Address as AddressProto, H256 as H256Proto,
+    },
+    FromProto, IntoProto,
+};
+use web3::types::{Address, H256};
+
+use self::proto::order_service_server::{OrderService, OrderServiceServer};
+use crate::{
+    api::{Auth, Limits, Scope, Submission, SubmissionStatus, SubmitMode, API_KEY_HEADER},
+    database::OrderFilter,
+    orders::{LimitOrder, Signature, SignedOrder},
+    ApiError, App,
+};
+
+mod proto {
+    #![allow(clippy::all, clippy::pedantic, clippy::nursery)]
+    tonic::include_proto!("zeroex");
+}
+
+/// Number of events buffered per subscriber
+const SUBSCRIBER_BUFFER: usize = 128;
+
+struct Service {
+    app:    Arc<App>,
+    limits: Arc<Limits>,
+    auth:   Arc<Auth>,
+}
+
+impl Service {
+    /// Check the API key in the request metadata for `scope` and its quota,
+    /// or the rate of the client address if the key has none
+    fn authorize<T>(&self, request: &Request<T>, scope: Scope) -> Result<(), Status> {
+        let metadata = |name| {
+            request
+                .metadata()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        let client = self.auth.authorize(metadata(API_KEY_HEADER), scope)?;
+        let remote = request
+            .remote_addr()
+            .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |address| address.ip());
+        let ip = self.limits.client(metadata("x-forwarded-for"), remote);
+        self.limits.admit(&client, ip)?;
+        Ok(())
+    }
+}
+
+impl From<ApiError> for Status {
+    fn from(error: ApiError) -> Self {
+        match error {
+            ApiError::Unauthorized => Self::unauthenticated(error.to_string()),
+            ApiError::Forbidden(_) => Self::permission_denied(error.to_string()),
+            ApiError::RateLimited => Self::resource_exhausted(error.to_string()),
+            ApiError::BodyTooLarge(_) | ApiError::TooManyOrders(_) => {
+                Self::invalid_argument(error.to_string())
+            }
+            _ => Self::internal(error.to_string()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl OrderService for Service {
+    type SubscribeOrderEventsStream = ReceiverStream<Result<OrderEventProto, Status>>;
+
+    #[allow(clippy::cast_possible_truncation)]
+    async fn submit_orders(
+        &self,
+        request: Request<SubmitOrdersRequest>,
+    ) -> Result<Response<SubmitOrdersResponse>, Status> {
+        self.authorize(&request, Scope::Submit)?;
+        let request = request.into_inner();
+        if request.encoded_len() > self.limits.max_body() {
+            return Err(ApiError::BodyTooLarge(self.limits.max_body()).into());
+        }
+        self.limits.check_count(request.orders.len())?;
+        let mode = if request.atomic {
+            SubmitMode::Atomic
+        } else {
+            SubmitMode::BestEffort
+        };
+        let orders = request
+            .orders
+            .into_iter()
+            .map(signed_order)
+            .collect::<Result<Vec<_>, _>>()?;
+        let submissions = self.app.orders(orders, mode, &self.limits).await?;
+        let errors = submissions
+            .iter()
+            .enumerate()
+            .flat_map(|(index, submission)| {
+                submission.errors.iter().map(move |error| {
+                    ValidationErrorProto {
+                        index:  index as u32,
+                        code:   error.error_code(),
+                        reason: error.to_string(),
+                    }
+                })
+            })
+            .collect();
+        let results = submissions.iter().map(order_submission).collect();
+        Ok(Response::new(SubmitOrdersResponse { errors, results }))
+    }
+
+    async fn get_order(
+        &self,
+        request: Request<GetOrderRequest>,
+    ) -> Result<Response<OrderEventProto>, Status> {
+        self.authorize(&request, Scope::Read)?;
+        let hash = request
+            .into_inner()
+            .hash
+            .filter(is_hash)
+            .map(H256::from_proto)
+            .ok_or_else(|| Status::invalid_argument("Missing or invalid order hash"))?;
+        match self.app.database.get_order(hash).await {
+            Ok(Some(order)) => Ok(Response::new(order.into_proto())),
+            Ok(None) => Err(Status::not_found(format!("Order {:?} not found", hash))),
+            Err(error) => Err(internal(&error)),
+        }
+    }
+
+    async fn query_orders(
+        &self,
+        request: Request<OrderFilterProto>,
+    ) -> Result<Response<QueryOrdersResponse>, Status> {
+        self.authorize(&request, Scope::Read)?;
+        let filter = order_filter(request.into_inner())?;
+        let orders = self
+            .app
+            .database
+            .query_orders(filter)
+            .await
+            .map_err(|error| internal(&error))?
+            .into_iter()
+            .map(IntoProto::into_proto)
+            .collect();
+        Ok(Response::new(QueryOrdersResponse { orders }))
+    }
+
+    async fn subscribe_order_events(
+        &self,
+        request: Request<OrderFilterProto>,
+    ) -> Result<Response<Self::SubscribeOrderEventsStream>, Status> {
+        self.authorize(&request, Scope::Stream)?;
+        let filter = order_filter(request.into_inner())?;
+        let mut events = self.app.subscribers.subscribe();
+        let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let item = match events.recv().await {
+                    Ok(event) if filter.matches(&event.order) => Ok(event.into_proto()),
+                    Ok(_) => continue,
+                    // Slow subscribers are disconnected rather than silently missing events
+                    Err(RecvError::Lagged(skipped)) => {
+                        Err(Status::data_loss(format!(
+                            "Subscriber lagged behind, {} events skipped",
+                            skipped
+                        )))
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let lagged = item.is_err();
+                if sender.send(item).await.is_err() || lagged {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+fn order_submission(submission: &Submission) -> OrderSubmissionProto {
+    let status = match submission.status {
+        SubmissionStatus::Accepted => SubmissionStatusProto::Accepted,
+        SubmissionStatus::Rejected => SubmissionStatusProto::Rejected,
+        SubmissionStatus::Skipped => SubmissionStatusProto::Skipped,
+    };
+    OrderSubmissionProto {
+        hash:   Some(submission.hash.into_proto()),
+        status: status.into(),
+    }
+}
+
+fn internal(error: &anyhow::Error) -> Status {
+    error!(?error, "Internal error in gRPC service");
+    Status::internal("Internal error")
+}
+
+fn is_address(address: &AddressProto) -> bool {
+    address.bytes.len() == Address::len_bytes()
+}
+
+fn is_hash(hash: &H256Proto) -> bool {
+    hash.bytes.len() == H256::len_bytes()
+}
+
+fn address(address: Option<AddressProto>) -> Result<Option<Address>, Status> {
+    match address {
+        None => Ok(None),
+        Some(address) if is_address(&address) => Ok(Some(Address::from_proto(address))),
+        Some(_) => Err(Status::invalid_argument("Invalid address")),
+    }
+}
+
+fn order_filter(filter: OrderFilterProto) -> Result<OrderFilter, Status> {
+    Ok(OrderFilter {
+        maker:       address(filter.maker)?,
+        maker_token: address(filter.maker_token)?,
+        taker_token: address(filter.taker_token)?,
+    })
+}
+
+/// Convert a submitted order, rejecting the incomplete or malformed ones that
+/// [`FromProto`] can not handle.
+fn signed_order(order: SignedOrderProto) -> Result<SignedOrder, Status> {
+    let invalid = || Status::invalid_argument("Incomplete or malformed order");
+    let limit_order = order.limit_order.ok_or_else(invalid)?;
+    let signature = order.signature.ok_or_else(invalid)?;
+    let address = |address: &Option<AddressProto>| address.as_ref().map_or(false, is_address);
+    let hash = |hash: &Option<H256Proto>| hash.as_ref().map_or(false, is_hash);
+    let valid = address(&limit_order.maker)
+        && address(&limit_order.taker)
+        && address(&limit_order.maker_token)
+        && address(&limit_order.taker_token)
+        && address(&limit_order.fee_recipient)
+        && address(&limit_order.sender)
+        && address(&limit_order.verifying_contract)
+        && hash(&limit_order.pool)
+        && limit_order.maker_amount.is_some()
+        && limit_order.taker_amount.is_some()
+        && limit_order.taker_token_fee_amount.is_some()
+        && limit_order.salt.is_some()
+        && hash(&signature.r)
+        && hash(&signature.s)
+        && u8::try_from(signature.v).is_ok()
+        && SignatureTypeProto::from_i32(signature.r#type).is_some();
+    if !valid {
+        return Err(invalid());
+    }
+    Ok(SignedOrder {
+        order:     LimitOrder::from_proto(limit_order),
+        signature: Signature::from_proto(signature),
+    })
+}
+
+/// Run a gRPC server on [`socket_address`]
+pub(super) async fn serve(
+    app: App,
+    socket_address: &SocketAddr,
+    limits: Arc<Limits>,
+    auth: Arc<Auth>,
+) -> AnyResult<()> {
+    let service = OrderServiceServer::new(Service {
+        app: Arc::new(app),
+        limits,
+        auth,
+    });
+    info!("Listening for gRPC on {}", socket_address);
+    Server::builder()
+        .add_service(service)
+        .serve(*socket_address)
+        .await
+        .context("internal server error in gRPC server")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reject_incomplete_order() {
+        let status = signed_order(SignedOrderProto::default()).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_order_submission() {
+        let submission = order_submission(&Submission {
+            hash:   H256::repeat_byte(1),
+            status: SubmissionStatus::Skipped,
+            errors: vec![],
+        });
+        assert_eq!(submission.hash, Some(H256::repeat_byte(1).into_proto()));
+        assert_eq!(submission.status(), SubmissionStatusProto::Skipped);
+    }
+
+    #[test]
+    fn test_order_filter() {
+        let filter = order_filter(OrderFilterProto {
+            maker: Some(Address::repeat_byte(1).into_proto()),
+            ..OrderFilterProto::default()
+        })
+        .unwrap();
+        assert_eq!(filter.maker, Some(Address::repeat_byte(1)));
+        assert_eq!(filter.maker_token, None);
+        assert!(order_filter(OrderFilterProto {
+            taker_token: Some(AddressProto { bytes: vec![1, 2] }),
+            ..OrderFilterProto::default()
+        })
+        .is_err());
+    }
+}
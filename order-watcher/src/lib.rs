@@ -4,41 +4,78 @@
 #[macro_use]
 extern crate diesel;
 
+mod access_list;
 mod api;
 mod database;
 mod ethereum;
-mod logging;
+mod grpc;
 mod orders;
+mod policy;
+mod replay;
 mod utils;
 
-use std::net::SocketAddr;
+use std::{
+    collections::HashSet,
+    mem,
+    net::SocketAddr,
+    num::NonZeroU64,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
 
-use anyhow::{Context as _, Result as AnyResult};
-use api::Error as ApiError;
-use block_watcher::{self, consumer::Consumer as BlockConsumer};
-use chrono::offset::Utc;
+use anyhow::{anyhow, Context as _, Error as AnyError, Result as AnyResult};
+use api::{Error as ApiError, Limits, Submission, SubmissionStatus, SubmitMode, Validation};
+use block_watcher::{
+    self,
+    consumer::{Consumer as BlockConsumer, FinalityConsumer},
+    Reorgable,
+};
+use chrono::{offset::Utc, DateTime};
 use ethabi::Address;
-use futures::stream::{self, StreamExt as _, TryStreamExt as _};
+use futures::{
+    future::{self, Future, FutureExt as _},
+    stream::{self, StreamExt as _, TryStreamExt as _},
+};
 use once_cell::sync::Lazy;
 use prometheus::{
     exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
     register_int_counter_vec, Histogram, HistogramVec, IntCounter, IntCounterVec,
 };
 use structopt::StructOpt;
-use tokio::{sync::oneshot, try_join};
+use tokio::{
+    sync::{broadcast, oneshot, Mutex},
+    try_join,
+};
 use tracing::{error, info, trace, warn};
-use types::{proto::zeroex::OrderEvent, IntoProto, KafkaProducer};
-use web3::types::U64;
+use types::{
+    proto::zeroex::{
+        order_event::Reason, BlockMarker, OrderEvent as OrderEventProto, OrderSnapshot,
+    },
+    IntoProto, KafkaProducer,
+};
+use web3::types::{BlockHeader, H256, U64};
 
+pub use crate::replay::{replay, ReplayOptions};
 use crate::{
+    access_list::SharedAccessList,
+    api::ValidationError,
     database::Database,
     ethereum::Ethereum,
-    orders::{Metadata, OrderStatus, SignedOrder, SignedOrderWithMetadata},
+    orders::{
+        change_reason, LimitOrder, Metadata, OrderEvent, OrderStatus, SignedOrder,
+        SignedOrderState, SignedOrderWithMetadata,
+    },
+    policy::Policy,
     utils::spawn_or_abort,
 };
 
-// Maximum number of blocks to process concurrently
-const MAX_CONCURRENT_BLOCKS: usize = 10;
+// Maximum number of order events buffered for slow gRPC subscribers
+const SUBSCRIBER_CAPACITY: usize = 1024;
 
 static REVALIDATION_LATENCY: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
@@ -87,6 +124,11 @@ pub struct Options {
     #[structopt(long, env = "ORDER_EVENT_TOPIC", default_value = "order_events")]
     order_event_topic: String,
 
+    /// Partition key of order events, either 'hash' or 'maker'. Events with
+    /// the same key are delivered in order.
+    #[structopt(long, env = "ORDER_EVENT_KEY", default_value = "hash")]
+    order_event_key: OrderEventKey,
+
     #[structopt(
         long,
         env = "BLOCK_WATCHER_TOPIC",
@@ -94,6 +136,33 @@ pub struct Options {
     )]
     block_watcher_topic: String,
 
+    /// Topic with finalized block headers. If set, invalid orders are only
+    /// deleted once their invalidation block is finalized instead of after
+    /// the maximum re-org depth.
+    #[structopt(long, env = "FINALIZED_TOPIC")]
+    finalized_topic: Option<String>,
+
+    /// Topic for full order book snapshots. Snapshots are not published if
+    /// unset.
+    #[structopt(long, env = "SNAPSHOT_TOPIC")]
+    snapshot_topic: Option<String>,
+
+    /// Publish a snapshot once this many blocks have passed since the last one
+    #[structopt(long, env = "SNAPSHOT_BLOCKS", default_value = "100")]
+    snapshot_blocks: NonZeroU64,
+
+    /// JSON file with `allowedTokens`, `deniedTokens` and `deniedMakers`
+    /// address lists, reloaded when it changes. Stored orders that become
+    /// blocked are invalidated and deleted like other invalid orders once
+    /// `max_reorg` blocks passed, so unblocking does not restore them.
+    #[structopt(long, env = "ACCESS_LIST")]
+    access_list: Option<PathBuf>,
+
+    /// JSON file with the order acceptance policy: expiry horizon, minimum
+    /// amounts by token, fee rules by pair and open orders per maker
+    #[structopt(long, env = "POLICY")]
+    policy: Option<PathBuf>,
+
     /// DevUtils contract address.
     #[structopt(
         long,
@@ -105,47 +174,150 @@ pub struct Options {
     /// Order submission server socket address
     #[structopt(long, env = "SUBMIT_SERVER", default_value = "127.0.0.1:8080")]
     submit_server: SocketAddr,
+
+    #[structopt(flatten)]
+    api: api::Options,
+
+    /// gRPC server socket address. The gRPC server is not started if unset.
+    #[structopt(long, env = "GRPC_SERVER")]
+    grpc_server: Option<SocketAddr>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OrderEventKey {
+    Hash,
+    Maker,
+}
+
+impl FromStr for OrderEventKey {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hash" => Ok(Self::Hash),
+            "maker" => Ok(Self::Maker),
+            _ => Err(anyhow!("Invalid order event key: {}", s)),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 struct App {
-    database: Database,
-    ethereum: Ethereum,
-    kafka:    types::KafkaProducer<OrderEvent>,
+    database:        Database,
+    ethereum:        Ethereum,
+    kafka:           types::KafkaProducer<OrderEventProto>,
+    event_key:       OrderEventKey,
+    publish_lock:    Arc<Mutex<()>>,
+    snapshots:       Option<types::KafkaProducer<OrderSnapshot>>,
+    snapshot_blocks: NonZeroU64,
+    /// Block number of the last published snapshot
+    last_snapshot:   Arc<AtomicU64>,
+    subscribers:     broadcast::Sender<OrderEvent>,
+    access_list:     SharedAccessList,
+    policy:          Arc<Policy>,
 }
 
 impl App {
     async fn connect(options: Options) -> AnyResult<Self> {
-        let (ethereum, kafka) = try_join!(
+        let (ethereum, (kafka, snapshots)) = try_join!(
             Ethereum::connect(options.ethereum),
-            new_producer(options.kafka, options.order_event_topic),
+            new_producers(
+                options.kafka,
+                options.order_event_topic,
+                options.snapshot_topic
+            ),
         )?;
         let database = Database::connect(options.database, ethereum.chain.chain_id).await?;
+        let access_list = SharedAccessList::load(options.access_list).await?;
+        let policy = Arc::new(Policy::load(options.policy).await?);
         Ok(Self {
             database,
             ethereum,
             kafka,
+            event_key: options.order_event_key,
+            publish_lock: Arc::default(),
+            snapshots,
+            snapshot_blocks: options.snapshot_blocks,
+            last_snapshot: Arc::default(),
+            subscribers: broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            access_list,
+            policy,
         })
     }
 
-    #[allow(clippy::large_types_passed_by_value)]
-    async fn order(&self, order: SignedOrder) -> Result<(), ApiError> {
-        let received = Utc::now();
+    /// Send an order event to Kafka, keyed according to `event_key`.
+    async fn send_event(&self, event: &OrderEvent) -> AnyResult<()> {
+        let order = &event.order;
+        let key = match self.event_key {
+            OrderEventKey::Hash => format!("{:?}", order.metadata.hash),
+            OrderEventKey::Maker => format!("{:?}", order.signed_order.order.maker),
+        };
+        self.kafka.send_keyed(&key, &event.into_proto()).await
+    }
 
-        // Validate order and fetch state
+    /// Validate a submitted order and fetch its state with priority.
+    #[allow(clippy::large_types_passed_by_value)]
+    async fn fetch_state(&self, order: SignedOrder) -> Result<SignedOrderState, ApiError> {
         order
             .order
             .validate(&self.ethereum.chain)
+            .and_then(|()| self.access_list.check(&order.order))
             .map_err(|e| ApiError::OrderInvalid(vec![e.into()]))?;
-        let state = self
-            .ethereum
+        self.check_policy(&order.order).await?;
+        self.ethereum
             .batcher
             .fetch_state(order, true)
             .await
             .map_err(|error| {
                 error!(?error, "Error fetching order state");
                 ApiError::InternalError
-            })?;
+            })
+    }
+
+    /// Fetch the states of submitted orders, after charging their makers, see
+    /// [`charge_and_fetch`].
+    async fn fetch_states(
+        &self,
+        orders: &[SignedOrder],
+        limits: &Limits,
+    ) -> Result<Vec<Result<SignedOrderState, ApiError>>, ApiError> {
+        charge_and_fetch(orders, limits, |order| self.fetch_state(order)).await
+    }
+
+    /// Check an order against the acceptance policy. The limit of valid
+    /// orders per maker is enforced again when the orders are stored, see
+    /// [`Database::insert_orders`].
+    async fn check_policy(&self, order: &LimitOrder) -> Result<(), ApiError> {
+        #[allow(clippy::cast_sign_loss)]
+        let now = Utc::now().timestamp() as u64;
+        self.policy
+            .check(order, now)
+            .map_err(|e| ApiError::OrderInvalid(vec![e]))?;
+        if let Some(max) = self.policy.max_orders_per_maker {
+            let open = self
+                .database
+                .count_orders(order.maker)
+                .await
+                .map_err(|error| {
+                    error!(?error, "Error counting maker orders");
+                    ApiError::InternalError
+                })?;
+            if open >= max {
+                return Err(ApiError::OrderInvalid(vec![ValidationError::OutOfRange]));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate an order with its fetched state and create its event, without
+    /// storing it.
+    #[allow(clippy::large_types_passed_by_value)]
+    fn prepare(
+        order: SignedOrder,
+        state: Result<SignedOrderState, ApiError>,
+        received: DateTime<Utc>,
+    ) -> Result<OrderEvent, ApiError> {
+        let state = state?;
         state
             .validate()
             .map_err(|e| ApiError::OrderInvalid(vec![e.into()]))?;
@@ -156,63 +328,154 @@ impl App {
             metadata:     Metadata {
                 hash:       state.hash,
                 remaining:  state.taker_asset_fillable_amount,
+                filled:     state.taker_asset_filled_amount,
                 status:     OrderStatus::Added,
                 created_at: received,
             },
         };
+        Ok(OrderEvent {
+            order:                     signed_order_with_metadata,
+            reason:                    Reason::Added,
+            block:                     None,
+            taker_asset_filled_amount: Some(state.taker_asset_filled_amount),
+            previous:                  None,
+        })
+    }
+
+    /// Insert the orders of prepared events into the database in one
+    /// transaction and emit the events. If makers would exceed their limit of
+    /// valid orders nothing is stored, and the hashes of the orders over the
+    /// limit are returned.
+    async fn add(&self, events: &[OrderEvent]) -> Result<Vec<H256>, ApiError> {
+        if events.is_empty() {
+            return Ok(vec![]);
+        }
 
         // Insert into database
-        self.database
-            .insert_order(signed_order_with_metadata)
+        let over_limit = self
+            .database
+            .insert_orders(
+                events.iter().map(|event| event.order).collect(),
+                self.policy.max_orders_per_maker,
+            )
             .await
             .map_err(|error| {
-                error!(?error, "Error inserting order");
+                error!(?error, "Error inserting orders");
                 ApiError::InternalError
             })?;
+        if !over_limit.is_empty() {
+            return Ok(over_limit);
+        }
 
-        // Emit event
-        self.kafka
-            .send(&signed_order_with_metadata.into_proto())
-            .await
-            .map_err(|error| {
-                error!(?error, "Error emitting order event");
-                ApiError::InternalError
-            })?;
+        // Emit events
+        self.publish(events, None).await.map_err(|error| {
+            error!(?error, "Error emitting order events");
+            ApiError::InternalError
+        })?;
 
+        Ok(vec![])
+    }
+
+    #[allow(clippy::large_types_passed_by_value)]
+    async fn order(&self, order: SignedOrder, limits: &Limits) -> Result<(), ApiError> {
+        let received = Utc::now();
+        let state = self.fetch_states(&[order], limits).await?.remove(0);
+        let event = Self::prepare(order, state, received)?;
+        if !self.add(&[event]).await?.is_empty() {
+            return Err(ApiError::OrderInvalid(vec![ValidationError::OutOfRange]));
+        }
         Ok(())
     }
 
-    async fn orders(&self, orders: Vec<SignedOrder>) -> Result<(), ApiError> {
-        // Process many orders concurrently
-        const CONCURRENT: usize = 32;
-        let results = stream::iter(orders.into_iter())
-            .map(|order| self.order(order))
-            .buffered(CONCURRENT)
-            .collect::<Vec<_>>()
-            .await;
-        let mut errors = vec![];
-        let mut failed = false;
-        for result in results {
-            match result {
-                Err(ApiError::OrderInvalid(errs)) => {
-                    failed = true;
-                    errors.extend(errs);
+    /// Submit a batch of orders, returning a result per order. Only internal
+    /// errors fail the whole batch.
+    async fn orders(
+        &self,
+        orders: Vec<SignedOrder>,
+        mode: SubmitMode,
+        limits: &Limits,
+    ) -> Result<Vec<Submission>, ApiError> {
+        let received = Utc::now();
+        let fetched = self.fetch_states(&orders, limits).await?;
+        let mut events = vec![];
+        let mut submissions = vec![];
+        for (order, state) in orders.into_iter().zip(fetched) {
+            let hash = order.hash();
+            let (status, errors) = match Self::prepare(order, state, received) {
+                Ok(event) => {
+                    events.push(event);
+                    (SubmissionStatus::Accepted, vec![])
                 }
-                Err(e) => {
-                    return Err(e);
+                Err(ApiError::OrderInvalid(errors)) => (SubmissionStatus::Rejected, errors),
+                Err(error) => return Err(error),
+            };
+            submissions.push(Submission {
+                hash,
+                status,
+                errors,
+            });
+        }
+        loop {
+            if !mode.apply(&mut submissions) {
+                return Ok(submissions);
+            }
+            // Orders over the maker limit are rejected and the rest retried
+            let over_limit = self.add(&events).await?;
+            if over_limit.is_empty() {
+                return Ok(submissions);
+            }
+            for submission in &mut submissions {
+                if over_limit.contains(&submission.hash) {
+                    submission.status = SubmissionStatus::Rejected;
+                    submission.errors = vec![ValidationError::OutOfRange];
                 }
-                Ok(_) => {}
             }
+            events.retain(|event| !over_limit.contains(&event.order.metadata.hash));
         }
-        if failed {
-            Err(ApiError::OrderInvalid(errors))
-        } else {
-            Ok(())
+    }
+
+    /// Validate an order like [`Self::order`] does, without storing or
+    /// publishing it.
+    fn validate_order(state: Result<SignedOrderState, ApiError>) -> Result<Validation, ApiError> {
+        match state {
+            Ok(state) => {
+                Ok(Validation {
+                    state:  Some(state),
+                    errors: state.validate().err().map(Into::into).into_iter().collect(),
+                })
+            }
+            Err(ApiError::OrderInvalid(errors)) => {
+                Ok(Validation {
+                    state: None,
+                    errors,
+                })
+            }
+            Err(error) => Err(error),
         }
     }
 
+    async fn validate_orders(
+        &self,
+        orders: Vec<SignedOrder>,
+        limits: &Limits,
+    ) -> Result<Vec<Validation>, ApiError> {
+        self.fetch_states(&orders, limits)
+            .await?
+            .into_iter()
+            .map(Self::validate_order)
+            .collect()
+    }
+
+    /// Fetch the new state of an order in the given block, which follows a
+    /// `reorg` if set. The resulting changes are applied with [`Self::apply`]
+    /// after the events are published.
     #[allow(clippy::large_types_passed_by_value)] // Takes ownership
-    async fn revalidate(&self, order: SignedOrderWithMetadata, block_number: U64) -> AnyResult<()> {
+    async fn revalidate(
+        &self,
+        order: SignedOrderWithMetadata,
+        block: (U64, H256),
+        reorg: bool,
+    ) -> AnyResult<Revalidation> {
         let _timer = REVALIDATION_STEP_DURATION // Observes on drop
             .with_label_values(&["revalidate_one"])
             .start_timer();
@@ -224,69 +487,276 @@ impl App {
         let step_timer = REVALIDATION_STEP_DURATION // Observes on drop
             .with_label_values(&["fetch_state"])
             .start_timer();
-        let new_state = self
+        let mut new_state = self
             .ethereum
             .batcher
             .fetch_state(order.signed_order, false)
             .await?;
+        // Orders no longer accepted by the access list are invalidated
+        let blocked = self.access_list.check(&order.signed_order.order).err();
+        if blocked.is_some() {
+            new_state.status = OrderStatus::Invalid;
+        }
         let mut new_order = order;
         new_order.metadata.remaining = new_state.taker_asset_fillable_amount;
+        new_order.metadata.filled = new_state.taker_asset_filled_amount;
         new_order.metadata.status = new_state.status;
         drop(step_timer);
 
         // Emit Kafka event if status changed (but not if it changed from one
         // unfillable state to another)
-        if new_order != order && (!was_invalid || new_state.status == OrderStatus::Fillable) {
-            let _step_timer = REVALIDATION_STEP_DURATION // Observes on drop
-                .with_label_values(&["kafka_event"])
-                .start_timer();
-            self.kafka.send(&new_order.into_proto()).await?;
-        }
+        let emit =
+            new_order != order && (!was_invalid || new_state.status == OrderStatus::Fillable);
 
-        // Update database
-        let hash = order.metadata.hash;
+        // Determine database update
         let step_timer = REVALIDATION_STEP_DURATION // Observes on drop
             .with_label_values(&["validate"])
             .start_timer();
-        let validity = new_state.validate();
+        let cause = if blocked.is_some() {
+            Reason::Blocked
+        } else {
+            change_reason(&order.metadata, &new_state, reorg)
+        };
+        let validity = blocked.map_or_else(|| new_state.validate(), Err);
         drop(step_timer);
-        match validity {
-            Ok(()) => {
-                if was_invalid || order.metadata.remaining != new_order.metadata.remaining {
-                    UNINVALIDATED.inc();
-                    let _step_timer = REVALIDATION_STEP_DURATION // Observes on drop
-                        .with_label_values(&["update_order"])
-                        .start_timer();
-                    self.database
-                        .update_order(hash, new_order.metadata.remaining)
-                        .await?;
-                }
+        let change = match validity {
+            Ok(()) => (was_invalid || new_order != order).then(|| Change::Update),
+            Err(reason) => (!was_invalid).then(|| Change::Invalidate(reason.into())),
+        };
+        let event = OrderEvent {
+            order:                     new_order,
+            reason:                    cause,
+            block:                     Some(block),
+            taker_asset_filled_amount: Some(new_state.taker_asset_filled_amount),
+            previous:                  Some(order.metadata),
+        };
+        Ok(Revalidation {
+            event,
+            emit,
+            change,
+        })
+    }
+
+    /// Apply the database change of a revalidated order.
+    async fn apply(&self, revalidation: &Revalidation, block_number: U64) -> AnyResult<()> {
+        let order = &revalidation.event.order;
+        let hash = order.metadata.hash;
+        match revalidation.change {
+            None => {}
+            Some(Change::Update) => {
+                UNINVALIDATED.inc();
+                let _step_timer = REVALIDATION_STEP_DURATION // Observes on drop
+                    .with_label_values(&["update_order"])
+                    .start_timer();
+                self.database
+                    .update_order(hash, order.metadata.remaining, order.metadata.filled)
+                    .await?;
             }
-            Err(reason) => {
-                if !was_invalid {
-                    INVALIDATION_REASON
-                        .with_label_values(&[reason.into()])
-                        .inc();
-                    let _step_timer =
-                        REVALIDATION_STEP_DURATION // Observes on drop
-                            .with_label_values(&["invalidate_order"])
-                            .start_timer();
-                    self.database.invalidate_order(hash, block_number).await?;
-                }
+            Some(Change::Invalidate(reason)) => {
+                INVALIDATION_REASON.with_label_values(&[reason]).inc();
+                let _step_timer = REVALIDATION_STEP_DURATION // Observes on drop
+                    .with_label_values(&["invalidate_order"])
+                    .start_timer();
+                self.database.invalidate_order(hash, block_number).await?;
             }
         }
         Ok(())
     }
+
+    /// Publish order events, followed by a marker event if a `block` is given.
+    ///
+    /// With a transactional producer the events are published atomically, so
+    /// consumers never see a partially published block.
+    async fn publish(&self, events: &[OrderEvent], block: Option<&BlockHeader>) -> AnyResult<()> {
+        let _step_timer = REVALIDATION_STEP_DURATION // Observes on drop
+            .with_label_values(&["kafka_event"])
+            .start_timer();
+
+        // Only one transaction can be open per producer.
+        let _lock = self.publish_lock.lock().await;
+        let transactional = self.kafka.is_transactional();
+        if transactional {
+            self.kafka.begin_transaction()?;
+        }
+        let result = async {
+            for event in events {
+                self.send_event(event).await?;
+            }
+            if let Some(header) = block {
+                self.kafka.send(&block_marker(header, events.len())).await?;
+            }
+            AnyResult::Ok(())
+        }
+        .await;
+        if transactional {
+            if result.is_ok() {
+                self.kafka.commit_transaction().await?;
+            } else {
+                self.kafka.abort_transaction().await?;
+            }
+        }
+        if result.is_ok() {
+            for event in events {
+                // Fails only if there are no gRPC subscribers
+                let _ = self.subscribers.send(*event);
+            }
+        }
+        result
+    }
+
+    /// Publish all fillable orders if a snapshot is due after `header`.
+    async fn snapshot(&self, header: &BlockHeader) -> AnyResult<()> {
+        let producer = match &self.snapshots {
+            Some(producer) => producer,
+            None => return Ok(()),
+        };
+        let number = header.number.unwrap_or_default().as_u64();
+        let last = self.last_snapshot.load(Ordering::Relaxed);
+        if !snapshot_due(last, number, self.snapshot_blocks) {
+            return Ok(());
+        }
+        let orders = self
+            .database
+            .get_orders(&self.ethereum.chain)
+            .await?
+            .into_iter()
+            .filter(|order| order.metadata.status == OrderStatus::Fillable)
+            .map(IntoProto::into_proto)
+            .collect::<Vec<_>>();
+        info!(
+            number,
+            orders = orders.len(),
+            "Publishing order book snapshot"
+        );
+        let snapshot = OrderSnapshot {
+            block_number: number,
+            block_hash: header.hash.map(IntoProto::into_proto),
+            created_at: Some(SystemTime::now().into()),
+            orders,
+        };
+        let transactional = producer.is_transactional();
+        if transactional {
+            producer.begin_transaction()?;
+        }
+        let result = producer.send(&snapshot).await;
+        if transactional {
+            if result.is_ok() {
+                producer.commit_transaction().await?;
+            } else {
+                producer.abort_transaction().await?;
+            }
+        }
+        result?;
+        self.last_snapshot.store(number, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Whether a snapshot is due at block `number`, given the block of the `last`
+/// snapshot. Skipped blocks do not skip snapshots.
+fn snapshot_due(last: u64, number: u64, snapshot_blocks: NonZeroU64) -> bool {
+    number >= last.saturating_add(snapshot_blocks.get())
+}
+
+/// Charge makers for the `orders` with a valid signature, then `fetch` the
+/// states of those concurrently. Signatures are recovered locally, so a
+/// request can neither spend the quota of someone else's address nor make
+/// Ethereum calls before it is charged.
+async fn charge_and_fetch<F, Fut>(
+    orders: &[SignedOrder],
+    limits: &Limits,
+    mut fetch: F,
+) -> Result<Vec<Result<SignedOrderState, ApiError>>, ApiError>
+where
+    F: FnMut(SignedOrder) -> Fut,
+    Fut: Future<Output = Result<SignedOrderState, ApiError>>,
+{
+    // Process many orders concurrently
+    const CONCURRENT: usize = 32;
+    let signed = orders
+        .iter()
+        .map(|order| order.validate_signature().map(|()| *order))
+        .collect::<Vec<_>>();
+    limits.charge_makers(signed.iter().flatten().map(|order| order.order.maker))?;
+    Ok(stream::iter(signed)
+        .map(|signed| {
+            match signed {
+                Ok(order) => fetch(order).left_future(),
+                Err(error) => {
+                    future::ready(Err(ApiError::OrderInvalid(vec![error.into()]))).right_future()
+                }
+            }
+        })
+        .buffered(CONCURRENT)
+        .collect()
+        .await)
+}
+
+/// The last block whose invalid orders can no longer be re-orged at block
+/// `number`, either the `finalized` block if tracked or `max_reorg` blocks
+/// back.
+fn deletion_cutoff(number: U64, max_reorg: usize, finalized: Option<u64>) -> U64 {
+    finalized.map_or_else(|| number.saturating_sub(U64::from(max_reorg)), U64::from)
+}
+
+/// Outcome of revalidating an order
+struct Revalidation {
+    event:  OrderEvent,
+    emit:   bool,
+    change: Option<Change>,
+}
+
+enum Change {
+    /// The order is valid, update its remaining amount
+    Update,
+    /// The order became invalid for the given reason
+    Invalidate(&'static str),
+}
+
+/// The event marking the end of the order events of a block
+fn block_marker(header: &BlockHeader, event_count: usize) -> OrderEventProto {
+    OrderEventProto {
+        block_marker: Some(BlockMarker {
+            number:      header.number.unwrap_or_default().as_u64(),
+            hash:        header.hash.map(IntoProto::into_proto),
+            event_count: event_count as u64,
+        }),
+        ..OrderEventProto::default()
+    }
 }
 
 #[allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
 pub async fn main(options: Options, shutdown: oneshot::Receiver<()>) -> AnyResult<()> {
     let serve_url = options.submit_server;
+    let api_options = options.api.clone();
+    let grpc_url = options.grpc_server;
     let max_reorg = options.ethereum.max_reorg;
     let block_watcher_kafka = options.kafka.clone();
     let block_watcher_topic = options.block_watcher_topic.clone();
+    let finalized_topic = options.finalized_topic.clone();
+    let finalized = Arc::new(AtomicU64::new(0));
 
     let app = App::connect(options).await?;
+    let limits = Arc::new(Limits::new(api_options.clone())?);
+    let auth = api::Auth::load(&api_options.auth, &app.database).await?;
+
+    // Green thread to track the finalized block
+    if let Some(finalized_topic) = finalized_topic.clone() {
+        let finalized = finalized.clone();
+        let block_watcher_kafka = block_watcher_kafka.clone();
+        spawn_or_abort(async move {
+            let block_consumer =
+                FinalityConsumer::new(finalized_topic, block_watcher_kafka).await?;
+            let mut block_stream = Box::pin(block_consumer.stream_with_offsets());
+            while let Some((header, offset)) = block_stream.next().await {
+                let number = header.number.unwrap_or_default();
+                info!(?number, "Received finalized block header");
+                finalized.fetch_max(number.as_u64(), Ordering::Relaxed);
+                block_consumer.commit(offset)?;
+            }
+            AnyResult::Ok(())
+        });
+    }
 
     // Green thread to re-validate orders on new blocks
     spawn_or_abort({
@@ -295,10 +765,29 @@ pub async fn main(options: Options, shutdown: oneshot::Receiver<()>) -> AnyResul
             let app = app.clone();
             let block_consumer =
                 BlockConsumer::new(block_watcher_topic, block_watcher_kafka).await?;
-            let block_stream = block_consumer.stream();
+            let block_consumer = &block_consumer;
+            // Re-orged orders are revalidated with the headers that follow,
+            // the first of which is flagged so restored orders are reported
+            // as such
+            let mut reorg = false;
+            let block_stream =
+                block_consumer
+                    .stream_with_offsets()
+                    .filter_map(move |(event, offset)| {
+                        future::ready(match event {
+                            Reorgable::Event(header) => {
+                                Some((header, offset, mem::take(&mut reorg)))
+                            }
+                            Reorgable::Reorg { block_height } => {
+                                info!(block_height, "Received re-org");
+                                reorg = true;
+                                None
+                            }
+                        })
+                    });
+            let publisher = app.clone();
             block_stream
-                .map(Ok)
-                .try_for_each_concurrent(Some(MAX_CONCURRENT_BLOCKS), move |header| {
+                .then(move |(header, offset, reorg)| {
                     let app = app.clone();
                     async move {
                         info!(
@@ -309,14 +798,6 @@ pub async fn main(options: Options, shutdown: oneshot::Receiver<()>) -> AnyResul
                         let _timer = REVALIDATION_LATENCY.start_timer(); // Observes on drop
                         trace!("Revalidating all orders");
 
-                        // Delete invalid orders that are older than the maximum re-org depth.
-                        let step_timer = REVALIDATION_STEP_DURATION
-                            .with_label_values(&["delete"])
-                            .start_timer();
-                        let block_number = header.number.unwrap();
-                        app.database.delete_orders(block_number - max_reorg).await?;
-                        drop(step_timer);
-
                         // Fetch all orders
                         let step_timer = REVALIDATION_STEP_DURATION
                             .with_label_values(&["get_orders"])
@@ -326,33 +807,113 @@ pub async fn main(options: Options, shutdown: oneshot::Receiver<()>) -> AnyResul
                         drop(step_timer);
 
                         // Handle concurrently
+                        let block = (header.number.unwrap(), header.hash.unwrap_or_default());
                         let step_timer = REVALIDATION_STEP_DURATION
                             .with_label_values(&["revalidate_all"])
                             .start_timer();
-                        stream::iter(signed_order_with_metadatas.into_iter())
-                            .map(Ok)
-                            .try_for_each_concurrent(None, |order| {
+                        let revalidations = future::try_join_all(
+                            signed_order_with_metadatas.into_iter().map(|order| {
                                 let step_timer = REVALIDATION_STEP_DURATION
                                     .with_label_values(&["clone"])
                                     .start_timer();
                                 let app = app.clone(); // TODO: Perf?
                                 drop(step_timer);
-                                async move { app.revalidate(order, block_number).await }
-                            })
-                            .await
-                            .context("Error revalidating orders")?;
+                                async move { app.revalidate(order, block, reorg).await }
+                            }),
+                        )
+                        .await
+                        .context("Error revalidating orders")?;
                         drop(step_timer);
                         trace!("Revalidation done.");
-                        Ok(())
+                        AnyResult::Ok((header, offset, revalidations))
+                    }
+                })
+                // Blocks are processed one at a time, so each is revalidated
+                // against the database state left by the block before it.
+                .try_for_each(move |(header, offset, revalidations)| {
+                    let app = publisher.clone();
+                    let finalized = finalized.clone();
+                    let use_finalized = finalized_topic.is_some();
+                    async move {
+                        // Invalid orders that can no longer be re-orged, either because
+                        // they are finalized or older than the maximum re-org depth, are
+                        // deleted. Their revalidation is dropped.
+                        let block_number = header.number.unwrap();
+                        let block = (block_number, header.hash.unwrap_or_default());
+                        let finalized = use_finalized.then(|| finalized.load(Ordering::Relaxed));
+                        let cutoff = deletion_cutoff(block_number, max_reorg, finalized);
+                        let deletable = app.database.deletable_orders(cutoff).await?;
+                        let deleted = deletable
+                            .iter()
+                            .map(|order| order.metadata.hash)
+                            .collect::<HashSet<_>>();
+                        let revalidations = revalidations
+                            .into_iter()
+                            .filter(|revalidation| {
+                                !deleted.contains(&revalidation.event.order.metadata.hash)
+                            })
+                            .collect::<Vec<_>>();
+
+                        // Publish all events of the block atomically, then
+                        // update the database.
+                        let events = revalidations
+                            .iter()
+                            .filter(|revalidation| revalidation.emit)
+                            .map(|revalidation| revalidation.event)
+                            .chain(
+                                deletable
+                                    .into_iter()
+                                    .map(|order| OrderEvent::removed(order, block)),
+                            )
+                            .collect::<Vec<_>>();
+                        app.publish(&events, Some(&header)).await?;
+
+                        // Delete exactly the orders a removal was published for
+                        let step_timer = REVALIDATION_STEP_DURATION
+                            .with_label_values(&["delete"])
+                            .start_timer();
+                        let removed = app
+                            .database
+                            .delete_orders(deleted.iter().copied().collect())
+                            .await?;
+                        if removed.len() != deleted.len() {
+                            warn!(
+                                published = deleted.len(),
+                                deleted = removed.len(),
+                                "Deleted orders differ from published removals"
+                            );
+                        }
+                        drop(step_timer);
+                        future::try_join_all(
+                            revalidations
+                                .iter()
+                                .map(|revalidation| app.apply(revalidation, block_number)),
+                        )
+                        .await?;
+                        if let Err(error) = app.snapshot(&header).await {
+                            error!(?error, "Error publishing order book snapshot");
+                        }
+                        block_consumer.commit(offset)
                     }
                 })
                 .await
         }
     });
 
+    // Start gRPC server
+    if let Some(grpc_url) = grpc_url {
+        let app = app.clone();
+        let limits = limits.clone();
+        let auth = auth.clone();
+        spawn_or_abort(async move {
+            grpc::serve(app, &grpc_url, limits, auth).await?;
+            AnyResult::Ok(())
+        });
+    }
+
     // Start submit server
     spawn_or_abort(async move {
-        api::serve(app, &serve_url).await?;
+        api::serve(app, &serve_url, limits, auth).await?;
         AnyResult::Ok(())
     });
 
@@ -364,12 +925,21 @@ pub async fn main(options: Options, shutdown: oneshot::Receiver<()>) -> AnyResul
     Ok(())
 }
 
-async fn new_producer(
+async fn new_producers(
     options: types::Options,
-    topic: String,
-) -> AnyResult<KafkaProducer<OrderEvent>> {
+    event_topic: String,
+    snapshot_topic: Option<String>,
+) -> AnyResult<(
+    KafkaProducer<OrderEventProto>,
+    Option<KafkaProducer<OrderSnapshot>>,
+)> {
     let kafka = types::Kafka::new(options).await?;
-    Ok(kafka.new_producer(&topic).await?)
+    let events = kafka.new_transactional_producer(&event_topic).await?;
+    let snapshots = match snapshot_topic {
+        Some(topic) => Some(kafka.new_transactional_producer(&topic).await?),
+        None => None,
+    };
+    Ok((events, snapshots))
 }
 
 #[cfg(test)]
@@ -389,6 +959,84 @@ pub mod test {
         });
     }
 
+    #[test]
+    fn test_snapshot_due() {
+        let blocks = NonZeroU64::new(100).unwrap();
+        assert!(snapshot_due(0, 13_000_042, blocks));
+        assert!(!snapshot_due(13_000_042, 13_000_043, blocks));
+        assert!(!snapshot_due(13_000_042, 13_000_141, blocks));
+        assert!(snapshot_due(13_000_042, 13_000_142, blocks));
+
+        // Blocks can be skipped, such as when several arrive at once
+        assert!(snapshot_due(13_000_042, 13_000_150, blocks));
+
+        // Re-orgs back past the last snapshot
+        assert!(!snapshot_due(13_000_042, 13_000_040, blocks));
+    }
+
+    #[test]
+    fn test_deletion_cutoff() {
+        assert_eq!(deletion_cutoff(100.into(), 10, None), U64::from(90));
+        assert_eq!(deletion_cutoff(100.into(), 10, Some(95)), U64::from(95));
+        // Chains shorter than the maximum re-org depth, such as fresh devnets
+        assert_eq!(deletion_cutoff(5.into(), 10, None), U64::zero());
+    }
+
+    #[tokio::test]
+    async fn test_charge_before_fetch() {
+        let limits = Limits::new(
+            api::Options::from_iter_safe(&["", "--api-maker-burst", "2", "--api-max-orders", "2"])
+                .unwrap(),
+        )
+        .unwrap();
+        // Example from <https://0x.org/docs/api#request-6>
+        let order = serde_json::from_value::<SignedOrder>(serde_json::json!({
+            "makerToken": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+            "takerToken": "0xe41d2489571d322189246dafa5ebde1f4699f498",
+            "makerAmount": "100000000000000",
+            "takerAmount": "2000000000000000000000",
+            "maker": "0x56EB0aD2dC746540Fab5C02478B31e2AA9DdC38C",
+            "taker": "0x0000000000000000000000000000000000000000",
+            "pool": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "expiry": "1614956256",
+            "salt": "2752094376750492926844965905320507011598275560670346196138937898764349624882",
+            "chainId": 1,
+            "verifyingContract": "0xdef1c0ded9bec7f1a1670819833240f027b25eff",
+            "takerTokenFeeAmount": "0",
+            "sender": "0x0000000000000000000000000000000000000000",
+            "feeRecipient": "0x0000000000000000000000000000000000000000",
+            "signature": {
+                "v": 27,
+                "r": "0x983a8a8dad663124a52609fe9aa82737f7f02d12ed951785f36b50906041794d",
+                "s": "0x5f18ae837be4732bcb3dd019104cf775f92b8740b275be510462a7aa62cdf252",
+                "signatureType": 3
+            }
+        }))
+        .unwrap();
+        let mut forged = order;
+        forged.order.salt += 1.into();
+
+        let mut fetched = vec![];
+        let mut fetch = |order: SignedOrder| {
+            fetched.push(order.hash());
+            future::ready(Err(ApiError::InternalError))
+        };
+
+        // Orders with an invalid signature are neither charged nor fetched
+        let states = charge_and_fetch(&[forged, order], &limits, &mut fetch)
+            .await
+            .unwrap();
+        assert!(matches!(states[0], Err(ApiError::OrderInvalid(_))));
+        assert!(matches!(states[1], Err(ApiError::InternalError)));
+
+        // Over the maker limit, no order is fetched
+        assert!(matches!(
+            charge_and_fetch(&[order, order], &limits, &mut fetch).await,
+            Err(ApiError::RateLimited)
+        ));
+        assert_eq!(fetched, vec![order.hash()]);
+    }
+
     #[test]
     #[traced_test]
     fn test_with_log_output() {
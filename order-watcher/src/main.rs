@@ -1,7 +1,6 @@
 #![warn(clippy::all, clippy::pedantic, clippy::cargo, clippy::nursery)]
 
 mod allocator;
-mod logging;
 mod prometheus;
 mod shutdown;
 
@@ -9,8 +8,9 @@ use anyhow::{Context as _, Result as AnyResult};
 use structopt::StructOpt;
 use tokio::{runtime, sync::oneshot};
 use tracing::info;
+use types::logging::LogOptions;
 
-use self::{allocator::Allocator, logging::LogOptions};
+use self::allocator::Allocator;
 
 const VERSION: &str = concat!(
     env!("CARGO_PKG_VERSION"),
@@ -46,6 +46,14 @@ struct Options {
     pub prometheus: prometheus::Options,
     #[structopt(flatten)]
     app:            lib::Options,
+    #[structopt(subcommand)]
+    command:        Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Rebuild or verify the order table from the order event log
+    Replay(lib::ReplayOptions),
 }
 
 fn main() -> AnyResult<()> {
@@ -57,7 +65,11 @@ fn main() -> AnyResult<()> {
     ALLOCATOR.start_metering();
 
     // Start log system
-    options.log.init()?;
+    options.log.init(
+        env!("CARGO_CRATE_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        env!("COMMIT_SHA"),
+    )?;
 
     // Launch Tokio runtime
     runtime::Builder::new_multi_thread()
@@ -65,6 +77,10 @@ fn main() -> AnyResult<()> {
         .build()
         .context("Error creating Tokio runtime")?
         .block_on(async {
+            if let Some(Command::Replay(replay)) = options.command {
+                return lib::replay(options.app, replay).await;
+            }
+
             // Start prometheus
             tokio::spawn(prometheus::main(options.prometheus));
 
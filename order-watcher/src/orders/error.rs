@@ -37,4 +37,8 @@ pub enum Error {
     Unfunded,
     #[error("ORDER_FULLY_FILLED: order already fully filled")]
     FullyFilled,
+    #[error("TOKEN_NOT_SUPPORTED: order maker or taker token is not accepted")]
+    UnsupportedToken,
+    #[error("MAKER_NOT_SUPPORTED: order maker address is not accepted")]
+    DeniedMaker,
 }
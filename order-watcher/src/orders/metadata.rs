@@ -1,5 +1,8 @@
+use std::convert::TryInto;
+
 use chrono::{offset::Utc, DateTime};
 use serde::{Deserialize, Serialize};
+use types::{proto::zeroex::Metadata as MetadataProto, IntoProto};
 use web3::types::{H256, U128};
 
 use crate::{orders::OrderStatus, utils::serde::u128_dec};
@@ -11,7 +14,27 @@ pub struct Metadata {
     pub hash:       H256,
     #[serde(rename = "remainingFillableTakerAmount", with = "u128_dec")]
     pub remaining:  U128,
+    /// Filled taker amount, to tell fills from balance changes. Not part of
+    /// the SRA metadata.
+    #[serde(skip)]
+    pub filled:     U128,
     #[serde(rename = "state")]
     pub status:     OrderStatus,
     pub created_at: DateTime<Utc>,
 }
+
+impl IntoProto for Metadata {
+    type Proto = MetadataProto;
+
+    fn into_proto(self) -> Self::Proto {
+        MetadataProto {
+            created_at:   Some(prost_types::Timestamp {
+                seconds: self.created_at.timestamp(),
+                nanos:   self.created_at.timestamp_subsec_nanos().try_into().unwrap(),
+            }),
+            hash:         Some(self.hash.into_proto()),
+            order_status: self.status.into_proto().into(),
+            remaining:    Some(self.remaining.into_proto()),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 mod error;
 mod limit_order;
 mod metadata;
+mod order_event;
 mod signature_type;
 mod signed_order;
 mod signed_order_state;
@@ -10,6 +11,7 @@ pub use self::{
     error::Error,
     limit_order::LimitOrder,
     metadata::Metadata,
+    order_event::{change_reason, OrderEvent},
     signature_type::SignatureType,
     signed_order::{Signature, SignedOrder},
     signed_order_state::{OrderStatus, SignedOrderState},
@@ -0,0 +1,230 @@
+use types::{
+    proto::zeroex::{order_event::Reason, OrderEvent as OrderEventProto},
+    IntoProto,
+};
+use web3::types::{H256, U128, U64};
+
+use super::{Error, Metadata, OrderStatus, SignedOrderState, SignedOrderWithMetadata};
+
+/// A change of an order, published to Kafka.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OrderEvent {
+    pub order:                     SignedOrderWithMetadata,
+    pub reason:                    Reason,
+    /// Number and hash of the block in which the change was observed
+    pub block:                     Option<(U64, H256)>,
+    /// Unknown for removed orders
+    pub taker_asset_filled_amount: Option<U128>,
+    /// Metadata before the change
+    pub previous:                  Option<Metadata>,
+}
+
+impl IntoProto for OrderEvent {
+    type Proto = OrderEventProto;
+
+    fn into_proto(self) -> Self::Proto {
+        OrderEventProto {
+            reason: self.reason.into(),
+            block_number: self.block.map_or(0, |(number, _)| number.as_u64()),
+            block_hash: self.block.map(|(_, hash)| hash.into_proto()),
+            taker_asset_filled_amount: self.taker_asset_filled_amount.map(IntoProto::into_proto),
+            previous: self.previous.map(IntoProto::into_proto),
+            ..self.order.into_proto()
+        }
+    }
+}
+
+impl OrderEvent {
+    /// Event for an order deleted from the database in `block`.
+    #[allow(clippy::large_types_passed_by_value)]
+    pub fn removed(order: SignedOrderWithMetadata, block: (U64, H256)) -> Self {
+        let mut removed = order;
+        removed.metadata.status = OrderStatus::Removed;
+        Self {
+            order:                     removed,
+            reason:                    Reason::Deleted,
+            block:                     Some(block),
+            taker_asset_filled_amount: None,
+            previous:                  Some(order.metadata),
+        }
+    }
+}
+
+/// Why an order changed from `previous` to `state` in a block, which follows
+/// a `reorg` if set. Fills are told apart from maker balance and allowance
+/// changes by the filled amount.
+pub fn change_reason(previous: &Metadata, state: &SignedOrderState, reorg: bool) -> Reason {
+    let was_valid = matches!(previous.status, OrderStatus::Added | OrderStatus::Fillable);
+    match state.validate() {
+        Err(Error::FullyFilled) => Reason::Filled,
+        Err(Error::Cancelled) => Reason::Cancelled,
+        Err(Error::Expired) => Reason::Expired,
+        Err(Error::Unfunded) => Reason::Unfunded,
+        Err(_) => Reason::Unspecified,
+        Ok(()) if state.taker_asset_filled_amount > previous.filled => Reason::PartiallyFilled,
+        Ok(()) => {
+            let restored = !was_valid
+                || state.taker_asset_filled_amount < previous.filled
+                || state.taker_asset_fillable_amount > previous.remaining;
+            if restored && reorg {
+                Reason::ReorgRestored
+            } else if !was_valid || state.taker_asset_fillable_amount > previous.remaining {
+                Reason::Funded
+            } else {
+                Reason::Unspecified
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn previous(status: OrderStatus, remaining: u64, filled: u64) -> Metadata {
+        Metadata {
+            hash: H256::zero(),
+            remaining: remaining.into(),
+            filled: filled.into(),
+            status,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn state(status: OrderStatus, fillable: u64, filled: u64) -> SignedOrderState {
+        SignedOrderState {
+            hash: H256::zero(),
+            status,
+            taker_asset_filled_amount: filled.into(),
+            taker_asset_fillable_amount: fillable.into(),
+            is_signature_valid: true,
+        }
+    }
+
+    #[test]
+    fn test_change_reason() {
+        let fillable = previous(OrderStatus::Fillable, 10, 5);
+        let invalid = previous(OrderStatus::Invalid, 10, 5);
+        let cases = [
+            (
+                fillable,
+                state(OrderStatus::FullyFilled, 0, 15),
+                Reason::Filled,
+            ),
+            (
+                fillable,
+                state(OrderStatus::Cancelled, 10, 5),
+                Reason::Cancelled,
+            ),
+            (
+                fillable,
+                state(OrderStatus::Expired, 10, 5),
+                Reason::Expired,
+            ),
+            (
+                fillable,
+                state(OrderStatus::Invalid, 10, 5),
+                Reason::Unfunded,
+            ),
+            (
+                fillable,
+                state(OrderStatus::Fillable, 5, 10),
+                Reason::PartiallyFilled,
+            ),
+            (invalid, state(OrderStatus::Fillable, 10, 5), Reason::Funded),
+        ];
+        for (previous, state, reason) in cases {
+            assert_eq!(change_reason(&previous, &state, false), reason);
+        }
+    }
+
+    #[test]
+    fn test_reorg_change_reason() {
+        let fillable = previous(OrderStatus::Fillable, 10, 5);
+        let invalid = previous(OrderStatus::Invalid, 10, 5);
+        let filled = previous(OrderStatus::FullyFilled, 0, 15);
+        let cases = [
+            // Fill re-orged out
+            (
+                fillable,
+                state(OrderStatus::Fillable, 15, 0),
+                Reason::ReorgRestored,
+                Reason::Funded,
+            ),
+            (
+                filled,
+                state(OrderStatus::Fillable, 10, 5),
+                Reason::ReorgRestored,
+                Reason::Funded,
+            ),
+            // Filled amount lower without a change in fillable amount
+            (
+                fillable,
+                state(OrderStatus::Fillable, 10, 0),
+                Reason::ReorgRestored,
+                Reason::Unspecified,
+            ),
+            // Balance decreased while a fill was re-orged out
+            (
+                fillable,
+                state(OrderStatus::Fillable, 8, 2),
+                Reason::ReorgRestored,
+                Reason::Unspecified,
+            ),
+            // Unfunding re-orged out or maker funded the order
+            (
+                invalid,
+                state(OrderStatus::Fillable, 10, 5),
+                Reason::ReorgRestored,
+                Reason::Funded,
+            ),
+            // Fills are reported as such in re-orged blocks too
+            (
+                fillable,
+                state(OrderStatus::Fillable, 5, 10),
+                Reason::PartiallyFilled,
+                Reason::PartiallyFilled,
+            ),
+            (
+                fillable,
+                state(OrderStatus::Fillable, 10, 5),
+                Reason::Unspecified,
+                Reason::Unspecified,
+            ),
+        ];
+        for (previous, state, reorg, no_reorg) in cases {
+            assert_eq!(change_reason(&previous, &state, true), reorg);
+            assert_eq!(change_reason(&previous, &state, false), no_reorg);
+        }
+    }
+
+    #[test]
+    fn test_balance_change_reason() {
+        let fillable = previous(OrderStatus::Fillable, 10, 5);
+        let cases = [
+            // Maker balance or allowance decreased
+            (
+                fillable,
+                state(OrderStatus::Fillable, 4, 5),
+                Reason::Unspecified,
+            ),
+            // Maker funded the order
+            (
+                fillable,
+                state(OrderStatus::Fillable, 15, 5),
+                Reason::Funded,
+            ),
+            // Filled while the maker balance increased
+            (
+                fillable,
+                state(OrderStatus::Fillable, 12, 8),
+                Reason::PartiallyFilled,
+            ),
+        ];
+        for (previous, state, reason) in cases {
+            assert_eq!(change_reason(&previous, &state, false), reason);
+        }
+    }
+}
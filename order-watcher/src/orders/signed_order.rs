@@ -49,7 +49,6 @@ impl Signature {
 }
 
 impl SignedOrder {
-    #[allow(dead_code)]
     pub fn hash(&self) -> H256 {
         self.order.hash()
     }
@@ -3,7 +3,7 @@ use types::{proto::zeroex::metadata::OrderStatus as OrderStatusProto, FromProto,
 use web3::types::{H256, U128};
 
 use super::Error;
-use crate::require;
+use crate::{require, utils::serde::u128_dec};
 
 // TODO: just use the proto enum instead.
 /// See <https://protocol.0x.org/en/latest/basics/functions.html#getlimitorderinfo>
@@ -16,6 +16,8 @@ pub enum OrderStatus {
     FullyFilled,
     Cancelled,
     Expired,
+    /// Deleted after being invalid for longer than a re-org can undo
+    Removed,
 }
 
 impl FromProto for OrderStatus {
@@ -29,6 +31,7 @@ impl FromProto for OrderStatus {
             OrderStatusProto::FullyFilled => Self::FullyFilled,
             OrderStatusProto::Cancelled => Self::Cancelled,
             OrderStatusProto::Expired => Self::Expired,
+            OrderStatusProto::Removed => Self::Removed,
         }
     }
 }
@@ -44,15 +47,20 @@ impl IntoProto for OrderStatus {
             Self::FullyFilled => OrderStatusProto::FullyFilled,
             Self::Cancelled => OrderStatusProto::Cancelled,
             Self::Expired => OrderStatusProto::Expired,
+            Self::Removed => OrderStatusProto::Removed,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct SignedOrderState {
+    #[serde(rename = "orderHash")]
     pub hash: H256,
     pub status: OrderStatus,
+    #[serde(with = "u128_dec")]
     pub taker_asset_filled_amount: U128,
+    #[serde(with = "u128_dec")]
     pub taker_asset_fillable_amount: U128,
     pub is_signature_valid: bool,
 }
@@ -62,7 +70,7 @@ impl SignedOrderState {
         require!(self.is_signature_valid, Error::InvalidSignature);
         match self.status {
             OrderStatus::Added | OrderStatus::Fillable => Ok(()),
-            OrderStatus::Invalid => Err(Error::Unfunded),
+            OrderStatus::Invalid | OrderStatus::Removed => Err(Error::Unfunded),
             OrderStatus::FullyFilled => Err(Error::FullyFilled),
             OrderStatus::Cancelled => Err(Error::Cancelled),
             OrderStatus::Expired => Err(Error::Expired),
@@ -3,10 +3,7 @@ use std::convert::TryInto;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use types::{
-    proto::zeroex::{
-        LimitOrder as LimitOrderProto, Metadata as MetadataProto, OrderEvent,
-        Signature as SignatureProto,
-    },
+    proto::zeroex::{LimitOrder as LimitOrderProto, OrderEvent, Signature as SignatureProto},
     FromProto, IntoProto,
 };
 use web3::types::{Address, H256, U128, U256};
@@ -22,57 +19,61 @@ pub struct SignedOrderWithMetadata {
     pub metadata:     Metadata,
 }
 
+impl FromProto for LimitOrder {
+    type Proto = LimitOrderProto;
+
+    fn from_proto(limit_order: Self::Proto) -> Self {
+        Self {
+            maker:                  limit_order.maker.map(Address::from_proto).unwrap(),
+            taker:                  limit_order.taker.map(Address::from_proto).unwrap(),
+            maker_token:            limit_order.maker_token.map(Address::from_proto).unwrap(),
+            taker_token:            limit_order.taker_token.map(Address::from_proto).unwrap(),
+            maker_amount:           limit_order.maker_amount.map(U128::from_proto).unwrap(),
+            taker_amount:           limit_order.taker_amount.map(U128::from_proto).unwrap(),
+            expiry:                 limit_order.expiry,
+            salt:                   limit_order.salt.map(U256::from_proto).unwrap(),
+            fee_recipient:          limit_order.fee_recipient.map(Address::from_proto).unwrap(),
+            pool:                   limit_order.pool.map(H256::from_proto).unwrap(),
+            sender:                 limit_order.sender.map(Address::from_proto).unwrap(),
+            verifying_contract:     limit_order
+                .verifying_contract
+                .map(Address::from_proto)
+                .unwrap(),
+            taker_token_fee_amount: limit_order
+                .taker_token_fee_amount
+                .map(U128::from_proto)
+                .unwrap(),
+            chain_id:               limit_order.chain_id,
+        }
+    }
+}
+
+impl FromProto for Signature {
+    type Proto = SignatureProto;
+
+    fn from_proto(signature: Self::Proto) -> Self {
+        Self {
+            r:              signature.r.map(H256::from_proto).unwrap(),
+            s:              signature.s.map(H256::from_proto).unwrap(),
+            v:              signature.v.try_into().unwrap(),
+            signature_type: SignatureType::from_proto(
+                types::proto::zeroex::signature::Type::from_i32(signature.r#type).unwrap(),
+            ),
+        }
+    }
+}
+
 impl FromProto for SignedOrderWithMetadata {
     type Proto = OrderEvent;
 
     fn from_proto(p: Self::Proto) -> Self {
-        let limit_order = p.limit_order.unwrap();
         let metadata = p.metadata.unwrap();
-        let signature = p.signature.unwrap();
-
         let created_at = metadata.created_at.unwrap();
 
         Self {
             signed_order: SignedOrder {
-                order:     LimitOrder {
-                    maker:                  limit_order.maker.map(Address::from_proto).unwrap(),
-                    taker:                  limit_order.taker.map(Address::from_proto).unwrap(),
-                    maker_token:            limit_order
-                        .maker_token
-                        .map(Address::from_proto)
-                        .unwrap(),
-                    taker_token:            limit_order
-                        .taker_token
-                        .map(Address::from_proto)
-                        .unwrap(),
-                    maker_amount:           limit_order.maker_amount.map(U128::from_proto).unwrap(),
-                    taker_amount:           limit_order.taker_amount.map(U128::from_proto).unwrap(),
-                    expiry:                 limit_order.expiry,
-                    salt:                   limit_order.salt.map(U256::from_proto).unwrap(),
-                    fee_recipient:          limit_order
-                        .fee_recipient
-                        .map(Address::from_proto)
-                        .unwrap(),
-                    pool:                   limit_order.pool.map(H256::from_proto).unwrap(),
-                    sender:                 limit_order.sender.map(Address::from_proto).unwrap(),
-                    verifying_contract:     limit_order
-                        .verifying_contract
-                        .map(Address::from_proto)
-                        .unwrap(),
-                    taker_token_fee_amount: limit_order
-                        .taker_token_fee_amount
-                        .map(U128::from_proto)
-                        .unwrap(),
-                    chain_id:               limit_order.chain_id,
-                },
-                signature: Signature {
-                    r:              signature.r.map(H256::from_proto).unwrap(),
-                    s:              signature.s.map(H256::from_proto).unwrap(),
-                    v:              signature.v.try_into().unwrap(),
-                    signature_type: SignatureType::from_proto(
-                        types::proto::zeroex::signature::Type::from_i32(signature.r#type).unwrap(),
-                    ),
-                },
+                order:     LimitOrder::from_proto(p.limit_order.unwrap()),
+                signature: Signature::from_proto(p.signature.unwrap()),
             },
             metadata:     Metadata {
                 hash:       H256::from_proto(metadata.hash.unwrap()),
@@ -84,6 +85,10 @@ impl FromProto for SignedOrderWithMetadata {
                     Utc,
                 ),
                 remaining:  U128::from_proto(metadata.remaining.unwrap()),
+                filled:     p
+                    .taker_asset_filled_amount
+                    .map(U128::from_proto)
+                    .unwrap_or_default(),
                 status:     OrderStatus::from_proto(
                     types::proto::zeroex::metadata::OrderStatus::from_i32(metadata.order_status)
                         .unwrap(),
@@ -115,21 +120,6 @@ impl IntoProto for SignedOrderWithMetadata {
             chain_id:               limit_order.chain_id,
         };
 
-        let metadata = self.metadata;
-        let metadata_proto = MetadataProto {
-            created_at:   Some(prost_types::Timestamp {
-                seconds: metadata.created_at.timestamp(),
-                nanos:   metadata
-                    .created_at
-                    .timestamp_subsec_nanos()
-                    .try_into()
-                    .unwrap(),
-            }),
-            hash:         Some(metadata.hash.into_proto()),
-            order_status: metadata.status.into_proto().into(),
-            remaining:    Some(metadata.remaining.into_proto()),
-        };
-
         let signature = self.signed_order.signature;
         let signature_proto = SignatureProto {
             r:      Some(signature.r.into_proto()),
@@ -140,8 +130,9 @@ impl IntoProto for SignedOrderWithMetadata {
 
         OrderEvent {
             limit_order: Some(limit_order),
-            metadata:    Some(metadata_proto),
-            signature:   Some(signature_proto),
+            metadata: Some(self.metadata.into_proto()),
+            signature: Some(signature_proto),
+            ..OrderEvent::default()
         }
     }
 }
@@ -162,6 +153,7 @@ pub mod test {
             metadata:     Metadata {
                 hash:       H256::default(),
                 remaining:  U128::default(),
+                filled:     U128::default(),
                 status:     OrderStatus::Fillable,
                 created_at: DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(0, 0), Utc),
             },
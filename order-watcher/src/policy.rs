@@ -0,0 +1,176 @@
+//! Acceptance policy for submitted orders, on top of [`LimitOrder::validate`].
+
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{Context as _, Result as AnyResult};
+use serde::Deserialize;
+use web3::types::{Address, U128};
+
+use crate::{api::ValidationError, orders::LimitOrder, require, utils::serde::u128_dec};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct Amount(#[serde(with = "u128_dec")] pub U128);
+
+/// Fee requirements of orders from `maker_token` to `taker_token`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FeeRule {
+    pub maker_token:            Address,
+    pub taker_token:            Address,
+    #[serde(default)]
+    pub fee_recipient:          Option<Address>,
+    #[serde(default)]
+    pub taker_token_fee_amount: Option<Amount>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Policy {
+    /// Minimum time until expiry in seconds
+    #[serde(default)]
+    pub min_expiry_seconds:   Option<u64>,
+    /// Maximum time until expiry in seconds
+    #[serde(default)]
+    pub max_expiry_seconds:   Option<u64>,
+    /// Minimum maker or taker amount by token
+    #[serde(default)]
+    pub min_amounts:          HashMap<Address, Amount>,
+    #[serde(default)]
+    pub fee_rules:            Vec<FeeRule>,
+    /// Maximum number of valid orders per maker, checked by the caller and
+    /// enforced when orders are stored
+    #[serde(default)]
+    pub max_orders_per_maker: Option<u64>,
+}
+
+impl Policy {
+    /// Load the policy from a JSON file, or accept all orders if unset.
+    pub async fn load(path: Option<PathBuf>) -> AnyResult<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("Error reading {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid order policy in {}", path.display()))
+    }
+
+    /// Check an order at unix time `now`.
+    pub fn check(&self, order: &LimitOrder, now: u64) -> Result<(), ValidationError> {
+        if let Some(min) = self.min_expiry_seconds {
+            require!(
+                order.expiry >= now.saturating_add(min),
+                ValidationError::OutOfRange
+            );
+        }
+        if let Some(max) = self.max_expiry_seconds {
+            require!(
+                order.expiry <= now.saturating_add(max),
+                ValidationError::OutOfRange
+            );
+        }
+        let below_min = |token, amount| {
+            self.min_amounts
+                .get(&token)
+                .map_or(false, |min: &Amount| amount < min.0)
+        };
+        require!(
+            !below_min(order.maker_token, order.maker_amount),
+            ValidationError::OutOfRange
+        );
+        require!(
+            !below_min(order.taker_token, order.taker_amount),
+            ValidationError::OutOfRange
+        );
+        let rules = self.fee_rules.iter().filter(|rule| {
+            rule.maker_token == order.maker_token && rule.taker_token == order.taker_token
+        });
+        for rule in rules {
+            if let Some(fee_recipient) = rule.fee_recipient {
+                require!(
+                    order.fee_recipient == fee_recipient,
+                    ValidationError::InvalidField
+                );
+            }
+            if let Some(fee) = rule.taker_token_fee_amount {
+                require!(
+                    order.taker_token_fee_amount >= fee.0,
+                    ValidationError::OutOfRange
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let policy: Policy = serde_json::from_str(
+            r#"{
+                "minExpirySeconds": 60,
+                "maxExpirySeconds": 3600,
+                "minAmounts": {"0x0101010101010101010101010101010101010101": "1000"},
+                "feeRules": [{
+                    "makerToken": "0x0101010101010101010101010101010101010101",
+                    "takerToken": "0x0202020202020202020202020202020202020202",
+                    "feeRecipient": "0x0303030303030303030303030303030303030303",
+                    "takerTokenFeeAmount": "10"
+                }]
+            }"#,
+        )
+        .unwrap();
+        let now = 1_000_000;
+        let order = LimitOrder {
+            maker_token: Address::repeat_byte(1),
+            taker_token: Address::repeat_byte(2),
+            maker_amount: 1000.into(),
+            taker_amount: 1.into(),
+            expiry: now + 600,
+            fee_recipient: Address::repeat_byte(3),
+            taker_token_fee_amount: 10.into(),
+            ..LimitOrder::default()
+        };
+        assert!(policy.check(&order, now).is_ok());
+        let violations = [
+            LimitOrder {
+                expiry: now + 10,
+                ..order
+            },
+            LimitOrder {
+                expiry: now + 7200,
+                ..order
+            },
+            LimitOrder {
+                maker_amount: 999.into(),
+                ..order
+            },
+            LimitOrder {
+                taker_token_fee_amount: 9.into(),
+                ..order
+            },
+        ];
+        for violation in &violations {
+            assert!(matches!(
+                policy.check(violation, now),
+                Err(ValidationError::OutOfRange)
+            ));
+        }
+        assert!(matches!(
+            policy.check(
+                &LimitOrder {
+                    fee_recipient: Address::zero(),
+                    ..order
+                },
+                now
+            ),
+            Err(ValidationError::InvalidField)
+        ));
+        assert!(Policy::default().check(&order, now).is_ok());
+    }
+}
@@ -0,0 +1,240 @@
+//! Rebuild or verify the order table from the order event log.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{anyhow, Context as _, Result as AnyResult};
+use futures::StreamExt as _;
+use structopt::StructOpt;
+use tracing::{info, warn};
+use types::{proto::zeroex::OrderEvent, FromProto, ReplayStart};
+use web3::types::{H256, U64};
+
+use crate::{
+    database::Database, ethereum::Ethereum, Options, OrderStatus, SignedOrderWithMetadata,
+};
+
+#[derive(Debug, PartialEq, StructOpt)]
+pub struct ReplayOptions {
+    /// Where to start reading the order event topic: 'beginning', an offset
+    /// (in every partition) or an RFC 3339 timestamp
+    #[structopt(long, default_value = "beginning")]
+    from: ReplayStart,
+
+    /// Read orders from an NDJSON file instead, one order with metadata (as
+    /// served by the API) per line
+    #[structopt(long)]
+    file: Option<PathBuf>,
+
+    /// Compare the replayed orders with the database instead of writing them
+    #[structopt(long)]
+    verify: bool,
+}
+
+/// Latest state of each order in the event log.
+#[derive(Debug, Default)]
+struct Replayed {
+    /// Orders with the block of their last event, or else the latest block
+    /// marker seen before it
+    orders: HashMap<H256, (SignedOrderWithMetadata, U64)>,
+    block:  U64,
+}
+
+impl Replayed {
+    fn add_event(&mut self, event: OrderEvent) {
+        if let Some(marker) = event.block_marker {
+            self.block = self.block.max(marker.number.into());
+            return;
+        }
+        // Events of API submissions have no block
+        let block = match event.block_number {
+            0 => self.block,
+            number => number.into(),
+        };
+        let order = SignedOrderWithMetadata::from_proto(event);
+        if order.metadata.status == OrderStatus::Removed {
+            self.orders.remove(&order.metadata.hash);
+        } else {
+            self.orders.insert(order.metadata.hash, (order, block));
+        }
+    }
+
+    fn add_order(&mut self, order: SignedOrderWithMetadata) {
+        self.orders.insert(order.metadata.hash, (order, self.block));
+    }
+}
+
+fn is_fillable(status: OrderStatus) -> bool {
+    matches!(status, OrderStatus::Added | OrderStatus::Fillable)
+}
+
+/// Replay the order events and rebuild or verify the order table.
+#[allow(clippy::missing_errors_doc)]
+pub async fn replay(options: Options, replay: ReplayOptions) -> AnyResult<()> {
+    let mut replayed = Replayed::default();
+    if let Some(path) = &replay.file {
+        info!(?path, "Replaying orders from file");
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Error reading {}", path.display()))?;
+        for (index, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let order = serde_json::from_str(line)
+                .with_context(|| format!("Invalid order on line {}", index + 1))?;
+            replayed.add_order(order);
+        }
+    } else {
+        info!(topic = %options.order_event_topic, from = ?replay.from, "Replaying order events");
+        let kafka = types::Kafka::new(options.kafka.clone()).await?;
+        let consumer = kafka
+            .new_replay::<OrderEvent>(&options.order_event_topic, replay.from)
+            .await?;
+        let mut events = Box::pin(consumer.stream_to_end());
+        while let Some(event) = events.next().await {
+            replayed.add_event(event?);
+        }
+    }
+    info!(orders = replayed.orders.len(), "Replayed order events");
+
+    let ethereum = Ethereum::connect(options.ethereum).await?;
+    let database = Database::connect(options.database, ethereum.chain.chain_id).await?;
+    if replay.verify {
+        verify(&database, &ethereum, &replayed).await
+    } else {
+        rebuild(&database, &replayed).await
+    }
+}
+
+/// Write the replayed orders to the database. Invalid orders are marked
+/// invalid since the last block seen before their event, so they are deleted
+/// as usual.
+async fn rebuild(database: &Database, replayed: &Replayed) -> AnyResult<()> {
+    for &(order, block) in replayed.orders.values() {
+        database.insert_order(order).await?;
+        if is_fillable(order.metadata.status) {
+            database
+                .update_order(
+                    order.metadata.hash,
+                    order.metadata.remaining,
+                    order.metadata.filled,
+                )
+                .await?;
+        } else {
+            database
+                .invalidate_order(order.metadata.hash, block)
+                .await?;
+        }
+    }
+    info!(orders = replayed.orders.len(), "Rebuilt order table");
+    Ok(())
+}
+
+/// Compare the replayed orders with the database and fail on differences.
+/// Invalid orders missing from the database are expected, as they are deleted
+/// eventually.
+async fn verify(database: &Database, ethereum: &Ethereum, replayed: &Replayed) -> AnyResult<()> {
+    let stored = database
+        .get_orders(&ethereum.chain)
+        .await?
+        .into_iter()
+        .map(|order| (order.metadata.hash, order))
+        .collect::<HashMap<_, _>>();
+    let mut differences = 0_usize;
+    for (hash, (order, _block)) in &replayed.orders {
+        let fillable = is_fillable(order.metadata.status);
+        match stored.get(hash) {
+            None if fillable => {
+                warn!(?hash, "Fillable order missing from database");
+                differences += 1;
+            }
+            None => {}
+            Some(stored) if fillable != is_fillable(stored.metadata.status) => {
+                warn!(
+                    ?hash,
+                    replayed = ?order.metadata.status,
+                    stored = ?stored.metadata.status,
+                    "Order validity differs"
+                );
+                differences += 1;
+            }
+            Some(stored) if fillable && stored.metadata.remaining != order.metadata.remaining => {
+                warn!(
+                    ?hash,
+                    replayed = %order.metadata.remaining,
+                    stored = %stored.metadata.remaining,
+                    "Order remaining amount differs"
+                );
+                differences += 1;
+            }
+            Some(_) => {}
+        }
+    }
+    for hash in stored.keys() {
+        if !replayed.orders.contains_key(hash) {
+            warn!(?hash, "Order in database has no events");
+            differences += 1;
+        }
+    }
+    if differences > 0 {
+        return Err(anyhow!(
+            "{} difference(s) between the event log and the database",
+            differences
+        ));
+    }
+    info!(orders = stored.len(), "Database matches the event log");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use types::{proto::zeroex::BlockMarker, IntoProto};
+
+    use super::*;
+    use crate::orders::{Metadata, SignedOrder};
+
+    fn order(hash: u8, status: OrderStatus) -> SignedOrderWithMetadata {
+        SignedOrderWithMetadata {
+            signed_order: SignedOrder::default(),
+            metadata:     Metadata {
+                hash: H256::repeat_byte(hash),
+                remaining: 1.into(),
+                filled: 0.into(),
+                status,
+                created_at: Utc::now(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_replay_latest_state() {
+        let mut replayed = Replayed::default();
+        replayed.add_event(order(1, OrderStatus::Added).into_proto());
+        replayed.add_event(order(2, OrderStatus::Added).into_proto());
+        replayed.add_event(OrderEvent {
+            block_marker: Some(BlockMarker {
+                number: 7,
+                ..BlockMarker::default()
+            }),
+            ..OrderEvent::default()
+        });
+        replayed.add_event(order(1, OrderStatus::FullyFilled).into_proto());
+        replayed.add_event(OrderEvent {
+            block_number: 9,
+            ..order(3, OrderStatus::Fillable).into_proto()
+        });
+        replayed.add_event(order(4, OrderStatus::Invalid).into_proto());
+        replayed.add_event(order(4, OrderStatus::Removed).into_proto());
+
+        assert_eq!(replayed.orders.len(), 3);
+        let (first, block) = replayed.orders[&H256::repeat_byte(1)];
+        assert_eq!(first.metadata.status, OrderStatus::FullyFilled);
+        assert_eq!(block, U64::from(7));
+        let (second, block) = replayed.orders[&H256::repeat_byte(2)];
+        assert_eq!(second.metadata.status, OrderStatus::Added);
+        assert_eq!(block, U64::zero());
+        let (_, block) = replayed.orders[&H256::repeat_byte(3)];
+        assert_eq!(block, U64::from(9));
+    }
+}
@@ -16,7 +16,16 @@ CREATE TABLE signed_orders_v4 (
     signature character varying NOT NULL,
     remaining_fillable_taker_amount character varying NOT NULL,
     created_at timestamp with time zone DEFAULT now() NOT NULL,
-    invalid_since bigint NULL
+    invalid_since bigint NULL,
+    taker_asset_filled_amount character varying DEFAULT '0' NOT NULL
+);
+
+CREATE TABLE api_keys (
+    key_hash character varying PRIMARY KEY,
+    name character varying NOT NULL,
+    scopes character varying NOT NULL,
+    rate double precision NULL,
+    burst integer NULL
 );
 
 INSERT INTO signed_orders_v4 VALUES ('0xef61a4e751a0f95e6aff1e66a74fc1cb3e9fdad7b957c219f41740d5eb1d2971', '0x56bc8fa2b2b48d7a9427f21565265c29a31a8bd4', '0x57ab1ec28d129707052df4df418d58a2d46d5f51', '1000000000000000000', '100000000000000000000', '0x461783a831e6db52d68ba2f3194f6fd1e0087e04', '0x0000000000000000000000000000000000000000', '0x0000000000000000000000000000000000000000000000000000000000000000', '1640905200', '99872729219878081814181869402925067169517434860352757768858414588102862596149', '0xdef1c0ded9bec7f1a1670819833240f027b25eff', '0', '0x0000000000000000000000000000000000000000', '0x0000000000000000000000000000000000000000', '2,0xfcbffd4505e0c5253792560e7c03378cfbba10453b7fc90afb8e1bd78c8fecee,0x07b0103dda18fa858471df5a329c1572ed793190a3c735c7eb864c84c82721c7,28', '100000000000000000000', '2021-04-05 10:08:59.138427+00');
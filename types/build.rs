@@ -1,4 +1,4 @@
-use std::{io::Error, result::Result};
+use std::{env, fmt::Write as _, fs, io::Error, path::PathBuf, result::Result};
 
 use glob::glob;
 
@@ -12,5 +12,23 @@ fn main() -> Result<(), Error> {
         .collect();
     // dbg!(proto_paths.clone());
     // dbg!(proto_directories.clone());
-    prost_build::compile_protos(&proto_paths, &proto_directories)
+    prost_build::compile_protos(&proto_paths, &proto_directories)?;
+
+    // Embed the sources for registering them with a schema registry
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let mut proto_files = String::from("pub const PROTO_FILES: &[(&str, &str)] = &[\n");
+    for path in &proto_paths {
+        let name = path.strip_prefix("protobuf").unwrap();
+        writeln!(
+            proto_files,
+            "    ({:?}, include_str!({:?})),",
+            name.to_str().unwrap(),
+            manifest_dir.join(path)
+        )
+        .unwrap();
+    }
+    proto_files.push_str("];\n");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out_dir.join("proto_files.rs"), proto_files)?;
+    Ok(())
 }
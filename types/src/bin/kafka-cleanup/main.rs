@@ -0,0 +1,53 @@
+//! Deletes expired large message objects of a Kafka topic from S3.
+
+use std::time::Duration;
+
+use anyhow::Result as AnyResult;
+use humantime::parse_duration;
+use structopt::StructOpt;
+use tracing::info;
+use types::logging::LogOptions;
+
+#[derive(Debug, StructOpt)]
+struct Options {
+    #[structopt(flatten)]
+    log: LogOptions,
+
+    #[structopt(flatten)]
+    kafka: types::Options,
+
+    /// Topic whose large message objects to clean up
+    #[structopt(long, env = "KAFKA_CLEANUP_TOPIC")]
+    topic: String,
+
+    /// Delete objects older than this
+    #[structopt(long, env = "KAFKA_CLEANUP_RETENTION", default_value = "7d", parse(try_from_str = parse_duration))]
+    retention: Duration,
+
+    /// Only list and count the expired objects
+    #[structopt(long)]
+    dry_run: bool,
+}
+
+#[tokio::main]
+async fn main() -> AnyResult<()> {
+    let options = Options::from_args();
+    options.log.init(
+        env!("CARGO_CRATE_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        option_env!("COMMIT_SHA").unwrap_or_default(),
+    )?;
+    let count = types::cleanup_large_messages(
+        &options.kafka,
+        &options.topic,
+        options.retention,
+        options.dry_run,
+    )
+    .await?;
+    if options.dry_run {
+        info!(count, "Expired objects");
+    } else {
+        info!(count, "Deleted expired objects");
+    }
+    Ok(())
+}
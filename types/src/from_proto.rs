@@ -1,4 +1,7 @@
-use web3::types::{Address, BlockHeader, H2048, H256, H64, U128, U256, U64};
+use thiserror::Error;
+use web3::types::{
+    Address, BlockHeader, Log, TransactionReceipt, H2048, H256, H64, U128, U256, U64,
+};
 
 pub trait FromProto {
     type Proto;
@@ -6,6 +9,22 @@ pub trait FromProto {
     fn from_proto(p: Self::Proto) -> Self;
 }
 
+/// Conversion of messages received from outside, which may be missing
+/// required fields.
+pub trait TryFromProto: Sized {
+    type Proto;
+
+    fn try_from_proto(p: Self::Proto) -> Result<Self, MissingField>;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Error)]
+#[error("missing required field {0}")]
+pub struct MissingField(pub &'static str);
+
+fn required<T>(field: Option<T>, name: &'static str) -> Result<T, MissingField> {
+    field.ok_or(MissingField(name))
+}
+
 impl FromProto for U128 {
     type Proto = crate::proto::U128;
 
@@ -54,29 +73,150 @@ impl FromProto for H2048 {
     }
 }
 
-// TODO: Derive this? https://docs.rs/syn/1.0.76/syn/index.html#example-of-a-custom-derive
 impl FromProto for BlockHeader {
     type Proto = crate::proto::BlockHeader;
 
     fn from_proto(p: Self::Proto) -> Self {
-        Self {
+        Self::try_from_proto(p).unwrap()
+    }
+}
+
+// TODO: Derive this? https://docs.rs/syn/1.0.76/syn/index.html#example-of-a-custom-derive
+impl TryFromProto for BlockHeader {
+    type Proto = crate::proto::BlockHeader;
+
+    fn try_from_proto(p: Self::Proto) -> Result<Self, MissingField> {
+        Ok(Self {
             hash:              p.hash.map(H256::from_proto),
-            parent_hash:       p.parent_hash.map(H256::from_proto).unwrap(),
-            uncles_hash:       p.uncles_hash.map(H256::from_proto).unwrap(),
-            author:            p.author.map(Address::from_proto).unwrap(),
-            state_root:        p.state_root.map(H256::from_proto).unwrap(),
-            transactions_root: p.transactions_root.map(H256::from_proto).unwrap(),
-            receipts_root:     p.receipts_root.map(H256::from_proto).unwrap(),
+            parent_hash:       required(p.parent_hash, "parent_hash").map(H256::from_proto)?,
+            uncles_hash:       required(p.uncles_hash, "uncles_hash").map(H256::from_proto)?,
+            author:            required(p.author, "author").map(Address::from_proto)?,
+            state_root:        required(p.state_root, "state_root").map(H256::from_proto)?,
+            transactions_root: required(p.transactions_root, "transactions_root")
+                .map(H256::from_proto)?,
+            receipts_root:     required(p.receipts_root, "receipts_root").map(H256::from_proto)?,
             number:            p.number.map(U64::from),
-            gas_used:          p.gas_used.map(U256::from_proto).unwrap(),
-            gas_limit:         p.gas_limit.map(U256::from_proto).unwrap(),
+            gas_used:          required(p.gas_used, "gas_used").map(U256::from_proto)?,
+            gas_limit:         required(p.gas_limit, "gas_limit").map(U256::from_proto)?,
             base_fee_per_gas:  p.base_fee_per_gas.map(U256::from_proto),
             extra_data:        p.extra_data.into(),
-            logs_bloom:        p.logs_bloom.map(H2048::from_proto).unwrap(),
-            timestamp:         p.timestamp.map(U256::from_proto).unwrap(),
-            difficulty:        p.difficulty.map(U256::from_proto).unwrap(),
+            logs_bloom:        required(p.logs_bloom, "logs_bloom").map(H2048::from_proto)?,
+            timestamp:         required(p.timestamp, "timestamp").map(U256::from_proto)?,
+            difficulty:        required(p.difficulty, "difficulty").map(U256::from_proto)?,
             mix_hash:          p.mix_hash.map(H256::from_proto),
             nonce:             p.nonce.map(H64::from_proto),
+        })
+    }
+}
+
+impl TryFromProto for Log {
+    type Proto = crate::proto::Log;
+
+    fn try_from_proto(p: Self::Proto) -> Result<Self, MissingField> {
+        Ok(Self {
+            address:               required(p.address, "address").map(Address::from_proto)?,
+            topics:                p.topics.into_iter().map(H256::from_proto).collect(),
+            data:                  p.data.into(),
+            block_hash:            p.block_hash.map(H256::from_proto),
+            block_number:          p.block_number.map(U64::from),
+            transaction_hash:      p.transaction_hash.map(H256::from_proto),
+            transaction_index:     p.transaction_index.map(U64::from),
+            log_index:             p.log_index.map(U256::from_proto),
+            transaction_log_index: p.transaction_log_index.map(U256::from_proto),
+            log_type:              p.log_type,
+            removed:               p.removed,
+        })
+    }
+}
+
+impl TryFromProto for TransactionReceipt {
+    type Proto = crate::proto::Receipt;
+
+    fn try_from_proto(p: Self::Proto) -> Result<Self, MissingField> {
+        Ok(Self {
+            transaction_hash:    required(p.transaction_hash, "transaction_hash")
+                .map(H256::from_proto)?,
+            transaction_index:   p.transaction_index.into(),
+            block_hash:          p.block_hash.map(H256::from_proto),
+            block_number:        p.block_number.map(U64::from),
+            from:                required(p.from, "from").map(Address::from_proto)?,
+            to:                  p.to.map(Address::from_proto),
+            cumulative_gas_used: required(p.cumulative_gas_used, "cumulative_gas_used")
+                .map(U256::from_proto)?,
+            gas_used:            p.gas_used.map(U256::from_proto),
+            contract_address:    p.contract_address.map(Address::from_proto),
+            logs:                p
+                .logs
+                .into_iter()
+                .map(Log::try_from_proto)
+                .collect::<Result<_, _>>()?,
+            status:              p.status.map(U64::from),
+            root:                p.root.map(H256::from_proto),
+            logs_bloom:          required(p.logs_bloom, "logs_bloom").map(H2048::from_proto)?,
+            transaction_type:    p.transaction_type.map(U64::from),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntoProto;
+
+    fn log() -> Log {
+        Log {
+            address:               Address::repeat_byte(1),
+            topics:                vec![H256::repeat_byte(2), H256::repeat_byte(3)],
+            data:                  vec![4, 5, 6].into(),
+            block_hash:            Some(H256::repeat_byte(7)),
+            block_number:          Some(8.into()),
+            transaction_hash:      Some(H256::repeat_byte(9)),
+            transaction_index:     Some(10.into()),
+            log_index:             Some(11.into()),
+            transaction_log_index: Some(12.into()),
+            log_type:              Some("mined".into()),
+            removed:               Some(false),
         }
     }
+
+    #[test]
+    fn test_log_roundtrip() {
+        let log = log();
+        assert_eq!(Log::try_from_proto(log.clone().into_proto()), Ok(log));
+    }
+
+    #[test]
+    fn test_receipt_roundtrip() {
+        let receipt = TransactionReceipt {
+            transaction_hash:    H256::repeat_byte(9),
+            transaction_index:   10.into(),
+            block_hash:          Some(H256::repeat_byte(7)),
+            block_number:        Some(8.into()),
+            from:                Address::repeat_byte(13),
+            to:                  Some(Address::repeat_byte(1)),
+            cumulative_gas_used: 14.into(),
+            gas_used:            Some(15.into()),
+            contract_address:    None,
+            logs:                vec![log()],
+            status:              Some(1.into()),
+            root:                None,
+            logs_bloom:          H2048::repeat_byte(16),
+            transaction_type:    Some(2.into()),
+        };
+        assert_eq!(
+            TransactionReceipt::try_from_proto(receipt.clone().into_proto()),
+            Ok(receipt)
+        );
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let mut proto = log().into_proto();
+        proto.address = None;
+        assert_eq!(Log::try_from_proto(proto), Err(MissingField("address")));
+        assert_eq!(
+            BlockHeader::try_from_proto(crate::proto::BlockHeader::default()),
+            Err(MissingField("parent_hash"))
+        );
+    }
 }
@@ -1,4 +1,4 @@
-use web3::types::{Address, BlockHeader, H2048, H256, H64, U128, U256};
+use web3::types::{Address, BlockHeader, Log, TransactionReceipt, H2048, H256, H64, U128, U256};
 
 pub trait IntoProto {
     type Proto;
@@ -94,3 +94,46 @@ impl IntoProto for BlockHeader {
         }
     }
 }
+
+impl IntoProto for Log {
+    type Proto = crate::proto::Log;
+
+    fn into_proto(self) -> Self::Proto {
+        Self::Proto {
+            address:               Some(self.address.into_proto()),
+            topics:                self.topics.into_iter().map(H256::into_proto).collect(),
+            data:                  self.data.0,
+            block_hash:            self.block_hash.map(|x| x.into_proto()),
+            block_number:          self.block_number.map(|x| x.as_u64()),
+            transaction_hash:      self.transaction_hash.map(|x| x.into_proto()),
+            transaction_index:     self.transaction_index.map(|x| x.as_u64()),
+            log_index:             self.log_index.map(|x| x.into_proto()),
+            transaction_log_index: self.transaction_log_index.map(|x| x.into_proto()),
+            log_type:              self.log_type,
+            removed:               self.removed,
+        }
+    }
+}
+
+impl IntoProto for TransactionReceipt {
+    type Proto = crate::proto::Receipt;
+
+    fn into_proto(self) -> Self::Proto {
+        Self::Proto {
+            transaction_hash:    Some(self.transaction_hash.into_proto()),
+            transaction_index:   self.transaction_index.as_u64(),
+            block_hash:          self.block_hash.map(|x| x.into_proto()),
+            block_number:        self.block_number.map(|x| x.as_u64()),
+            from:                Some(self.from.into_proto()),
+            to:                  self.to.map(|x| x.into_proto()),
+            cumulative_gas_used: Some(self.cumulative_gas_used.into_proto()),
+            gas_used:            self.gas_used.map(|x| x.into_proto()),
+            contract_address:    self.contract_address.map(|x| x.into_proto()),
+            logs:                self.logs.into_iter().map(Log::into_proto).collect(),
+            status:              self.status.map(|x| x.as_u64()),
+            root:                self.root.map(|x| x.into_proto()),
+            logs_bloom:          Some(self.logs_bloom.into_proto()),
+            transaction_type:    self.transaction_type.map(|x| x.as_u64()),
+        }
+    }
+}
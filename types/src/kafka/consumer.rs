@@ -1,22 +1,81 @@
-use core::fmt::{Debug, Formatter, Result as FmtResult};
-use std::{any::type_name, marker::PhantomData, sync::Arc};
+use core::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    str::FromStr,
+};
+use std::{any::type_name, collections::HashSet, env, marker::PhantomData, sync::Arc};
 
 use anyhow::{anyhow, Context as _, Error as AnyError, Result as AnyResult};
-use futures::{stream::Stream, TryStreamExt};
+use chrono::{DateTime, Utc};
+use futures::{
+    future::ready,
+    stream::{self, Stream},
+    StreamExt, TryStreamExt,
+};
 use prost::Message;
 use rdkafka::{
-    consumer::{stream_consumer::StreamConsumer, Consumer},
-    ClientConfig, Message as _,
+    consumer::{stream_consumer::StreamConsumer, CommitMode, Consumer},
+    error::KafkaError,
+    message::BorrowedMessage,
+    metadata::MetadataPartition,
+    Message as _, Offset, TopicPartitionList,
 };
+use tokio::task::spawn_blocking;
+
+use super::{
+    dead_letter::DeadLetter, registry::decode_header, storage::Storage, Kafka, OffsetCommit,
+    METADATA_TIMEOUT,
+};
+use crate::{proto::large::Compression, MaybeLarge};
+
+/// Position of a consumed message. Commit it with [`KafkaConsumer::commit`]
+/// once the message is processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KafkaOffset {
+    partition: i32,
+    offset:    i64,
+}
+
+impl KafkaOffset {
+    fn of(message: &BorrowedMessage) -> Self {
+        Self {
+            partition: message.partition(),
+            offset:    message.offset(),
+        }
+    }
+}
+
+/// Where [`KafkaConsumer::new_replay`] starts reading each partition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayStart {
+    Beginning,
+    Offset(i64),
+    /// The first message at or after the timestamp
+    Timestamp(DateTime<Utc>),
+}
 
-use super::{storage::Storage, Kafka};
-use crate::proto;
+impl FromStr for ReplayStart {
+    type Err = AnyError;
+
+    /// Parses 'beginning', an offset or an RFC 3339 timestamp.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "beginning" {
+            return Ok(Self::Beginning);
+        }
+        if let Ok(offset) = s.parse() {
+            return Ok(Self::Offset(offset));
+        }
+        let timestamp = DateTime::parse_from_rfc3339(s)
+            .with_context(|| format!("Invalid replay start: {}", s))?;
+        Ok(Self::Timestamp(timestamp.with_timezone(&Utc)))
+    }
+}
 
 pub struct KafkaConsumer<T: Message + Default + Send + Sync> {
-    client:   Kafka,
-    consumer: Arc<StreamConsumer>,
-    topic:    String,
-    phantom:  PhantomData<T>,
+    client:      Kafka,
+    consumer:    Arc<StreamConsumer>,
+    topic:       String,
+    dead_letter: Arc<DeadLetter>,
+    phantom:     PhantomData<T>,
 }
 
 impl<T: Message + Default + Send + Sync> Debug for KafkaConsumer<T> {
@@ -40,26 +99,108 @@ impl<T: Message + Default + Send + Sync> KafkaConsumer<T> {
     pub fn new(client: &Kafka, topic: &str) -> AnyResult<Self> {
         let client = client.clone();
         let topic = topic.to_string();
-        let consumer: StreamConsumer = ClientConfig::new()
-            .set("bootstrap.servers", &client.options.kafka_brokers)
-            .set("group.id", "Consumer")
+        let auto_commit = match client.options.kafka_commit {
+            OffsetCommit::Auto => "true",
+            OffsetCommit::Manual => "false",
+        };
+        let group_id = group_id(client.options.kafka_group_id.as_deref());
+        let consumer: StreamConsumer = client
+            .options
+            .client_config(&[
+                ("group.id", group_id.as_str()),
+                (
+                    "auto.offset.reset",
+                    client.options.kafka_offset_reset.as_str(),
+                ),
+                ("enable.auto.commit", auto_commit),
+            ])
             .create()
             .context("Error creating Kafka Consumer")?;
         consumer.subscribe(&[&topic])?;
+        let dead_letter = DeadLetter::new(&client.options)?;
         Ok(Self {
             client,
             consumer: Arc::new(consumer),
             topic,
+            dead_letter: Arc::new(dead_letter),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Create a consumer reading all partitions of `topic` from `start`,
+    /// without joining the consumer group or committing offsets. Read it
+    /// with [`Self::stream_to_end`].
+    pub async fn new_replay(client: &Kafka, topic: &str, start: ReplayStart) -> AnyResult<Self> {
+        let client = client.clone();
+        let topic = topic.to_string();
+        let group_id = group_id(client.options.kafka_group_id.as_deref());
+        let consumer: StreamConsumer = client
+            .options
+            .client_config(&[
+                ("group.id", group_id.as_str()),
+                ("enable.auto.commit", "false"),
+                ("enable.partition.eof", "true"),
+            ])
+            .create()
+            .context("Error creating Kafka Consumer")?;
+        let consumer = Arc::new(consumer);
+
+        // Assign all partitions at their start offset
+        spawn_blocking({
+            let consumer = consumer.clone();
+            let topic = topic.clone();
+            move || {
+                let metadata = consumer
+                    .fetch_metadata(Some(&topic), METADATA_TIMEOUT)
+                    .context("Error fetching metadata")?;
+                let partitions = metadata
+                    .topics()
+                    .iter()
+                    .flat_map(|topic| topic.partitions())
+                    .map(MetadataPartition::id)
+                    .collect::<Vec<_>>();
+                if partitions.is_empty() {
+                    return Err(anyhow!("Kafka topic {} has no partitions", topic));
+                }
+                let offset = match start {
+                    ReplayStart::Beginning => Offset::Beginning,
+                    ReplayStart::Offset(offset) => Offset::Offset(offset),
+                    ReplayStart::Timestamp(time) => Offset::Offset(time.timestamp_millis()),
+                };
+                let mut list = TopicPartitionList::new();
+                for partition in partitions {
+                    list.add_partition_offset(&topic, partition, offset)?;
+                }
+                if let ReplayStart::Timestamp(_) = start {
+                    list = consumer
+                        .offsets_for_times(list, METADATA_TIMEOUT)
+                        .context("Error looking up offsets by time")?;
+                }
+                consumer
+                    .assign(&list)
+                    .context("Error assigning Kafka partitions")?;
+                AnyResult::Ok(())
+            }
+        })
+        .await??;
+
+        let dead_letter = DeadLetter::new(&client.options)?;
+        Ok(Self {
+            client,
+            consumer,
+            topic,
+            dead_letter: Arc::new(dead_letter),
             phantom: PhantomData,
         })
     }
 
     pub fn share(&self) -> Self {
         Self {
-            client:   self.client.clone(),
-            consumer: self.consumer.clone(),
-            topic:    self.topic.clone(),
-            phantom:  PhantomData,
+            client:      self.client.clone(),
+            consumer:    self.consumer.clone(),
+            topic:       self.topic.clone(),
+            dead_letter: self.dead_letter.clone(),
+            phantom:     PhantomData,
         }
     }
 
@@ -67,53 +208,236 @@ impl<T: Message + Default + Send + Sync> KafkaConsumer<T> {
         Self::new(&self.client, &self.topic)
     }
 
+    /// Stream of messages. In manual commit mode messages are committed when
+    /// delivered, use [`Self::stream_with_offsets`] to commit processed
+    /// messages instead.
     pub fn stream(&self) -> impl Stream<Item = Result<T, AnyError>> + '_ {
-        self.consumer.stream().err_into::<AnyError>().and_then({
-            let topic = self.topic.clone();
-            let storage = Arc::new(self.client.storage.clone());
-            move |message| {
-                let topic = topic.clone();
-                let storage = storage.clone();
+        self.stream_with_offsets()
+            .and_then(move |(message, offset)| ready(self.commit(offset).map(|()| message)))
+    }
+
+    /// Stream of messages with their offsets for [`Self::commit`]. Messages
+    /// that can not be decoded or fetched are sent to the dead-letter topic
+    /// and skipped.
+    pub fn stream_with_offsets(
+        &self,
+    ) -> impl Stream<Item = Result<(T, KafkaOffset), AnyError>> + '_ {
+        self.consumer
+            .stream()
+            .err_into::<AnyError>()
+            .try_filter_map(move |message| {
                 async move {
-                    let payload = message
-                        .payload()
-                        .ok_or_else(|| anyhow!("Kafka message missing payload"))?;
-                    let message = Self::fetch(&topic, &storage, payload).await?;
-                    Ok(message)
+                    let offset = KafkaOffset::of(&message);
+                    Ok(self
+                        .process(&message)
+                        .await?
+                        .map(|message| (message, offset)))
                 }
-            }
-        })
+            })
+    }
+
+    /// Stream of messages until the end of all assigned partitions is
+    /// reached. Only for consumers created with [`Self::new_replay`].
+    pub fn stream_to_end(&self) -> impl Stream<Item = Result<T, AnyError>> + '_ {
+        let remaining = self
+            .consumer
+            .assignment()
+            .map(|list| {
+                list.elements()
+                    .iter()
+                    .map(|element| element.partition())
+                    .collect::<HashSet<_>>()
+            })
+            .unwrap_or_default();
+        stream::unfold(
+            (self.consumer.stream(), remaining),
+            move |(mut messages, mut remaining)| {
+                async move {
+                    while !remaining.is_empty() {
+                        let result = match messages.next().await? {
+                            Ok(message) => self.process(&message).await.transpose(),
+                            Err(KafkaError::PartitionEOF(partition)) => {
+                                remaining.remove(&partition);
+                                None
+                            }
+                            Err(error) => Some(Err(error.into())),
+                        };
+                        if let Some(result) = result {
+                            return Some((result, (messages, remaining)));
+                        }
+                    }
+                    None
+                }
+            },
+        )
     }
 
+    /// Receive the next message, skipping messages that can not be decoded or
+    /// fetched. In manual commit mode the message is committed when received.
     pub async fn receive(&self) -> AnyResult<T> {
-        let message = self.consumer.recv().await?;
-        let payload = message
-            .payload()
-            .ok_or_else(|| anyhow!("Kafka message missing payload"))?;
-        let message = Self::fetch(&self.topic, &self.client.storage, payload).await?;
-        Ok(message)
-    }
-
-    async fn fetch(topic: &str, storage: &Storage, raw: &[u8]) -> AnyResult<T> {
-        // Get the MaybeLarge message
-        let maybe_large =
-            proto::MaybeLarge::decode(raw).context("Error decoding MaybeLarge message")?;
-
-        // Fetch the bytes for the embedded message (either directly or from storage)
-        let bytes = match maybe_large.maybe_large {
-            Some(proto::maybe_large::MaybeLarge::Embedded(bytes)) => bytes,
-            Some(proto::maybe_large::MaybeLarge::Large(proto::Large { payload_path })) => {
-                let topic_prefixed = format!("{}/{}", topic, &payload_path);
-                storage.download(topic_prefixed).await?
+        loop {
+            let message = self.consumer.recv().await?;
+            let offset = KafkaOffset::of(&message);
+            if let Some(message) = self.process(&message).await? {
+                self.commit(offset)?;
+                return Ok(message);
             }
-            None => {
-                return Err(anyhow!("MaybeLarge message missing field maybe_large"));
+        }
+    }
+
+    /// Decode or fetch a message, or send it to the dead-letter topic if that
+    /// fails.
+    async fn process(&self, message: &BorrowedMessage<'_>) -> AnyResult<Option<T>> {
+        let result = match message.payload() {
+            Some(payload) => {
+                Self::fetch(
+                    &self.topic,
+                    &self.client.storage,
+                    self.wire_format(),
+                    payload,
+                )
+                .await
             }
+            None => Err(anyhow!("Kafka message missing payload")),
         };
+        match result {
+            Ok(message) => Ok(Some(message)),
+            Err(error) => {
+                self.dead_letter.send(message, &error).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Commit all messages up to and including `offset` in its partition.
+    /// This is a no-op in auto commit mode.
+    pub fn commit(&self, offset: KafkaOffset) -> AnyResult<()> {
+        if self.client.options.kafka_commit == OffsetCommit::Auto {
+            return Ok(());
+        }
+        // The committed offset is the next message to read.
+        let mut list = TopicPartitionList::new();
+        list.add_partition_offset(
+            &self.topic,
+            offset.partition,
+            Offset::Offset(offset.offset + 1),
+        )?;
+        self.consumer
+            .commit(&list, CommitMode::Async)
+            .context("Error committing Kafka offset")?;
+        Ok(())
+    }
+
+    /// Whether messages use the Confluent wire format
+    fn wire_format(&self) -> bool {
+        self.client.options.kafka_schema_registry.is_some()
+    }
+
+    async fn fetch(topic: &str, storage: &Storage, wire_format: bool, raw: &[u8]) -> AnyResult<T> {
+        if wire_format {
+            let (_schema_id, bytes) = decode_header(raw)?;
+            return T::decode(bytes)
+                .with_context(|| format!("Error decoding {} message", type_name::<T>()));
+        }
+
+        // Decode the embedded message, or fetch it from storage
+        let maybe_large = MaybeLarge::<T>::decode(raw)
+            .with_context(|| format!("Error decoding MaybeLarge<{}> message", type_name::<T>()))?;
+        match maybe_large {
+            MaybeLarge::Embedded(message) => Ok(message),
+            MaybeLarge::Large(large) => {
+                let topic_prefixed = format!("{}/{}", topic, &large.payload_path);
+                let mut bytes = storage.download(topic_prefixed).await?;
+                if large.compression() == Compression::Zstd {
+                    bytes = zstd::decode_all(bytes.as_slice())
+                        .context("Error decompressing message")?;
+                }
+                T::decode(bytes.as_slice())
+                    .with_context(|| format!("Error decoding {} message", type_name::<T>()))
+            }
+        }
+    }
+}
+
+/// The consumer group id, defaulting to the name of the executable so that
+/// different services do not share a group.
+fn group_id(configured: Option<&str>) -> String {
+    configured.map_or_else(
+        || {
+            env::current_exe()
+                .ok()
+                .and_then(|path| path.file_stem()?.to_str().map(ToString::to_string))
+                .unwrap_or_else(|| "Consumer".to_string())
+        },
+        ToString::to_string,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{
+        kafka::storage::{tests::stub_storage, Options as StorageOptions},
+        proto, IntoProto,
+    };
+
+    fn event() -> proto::H256 {
+        web3::types::H256::repeat_byte(0x42).into_proto()
+    }
 
-        // Decode inner message
-        let message = T::decode(bytes.as_slice())
-            .with_context(|| format!("Error decoding {} message", type_name::<T>()))?;
-        Ok(message)
+    async fn fetch(storage: &Storage, raw: &[u8]) -> proto::H256 {
+        KafkaConsumer::<proto::H256>::fetch("topic", storage, false, raw)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_replay_start() {
+        assert_eq!(
+            "beginning".parse::<ReplayStart>().unwrap(),
+            ReplayStart::Beginning
+        );
+        assert_eq!(
+            "42".parse::<ReplayStart>().unwrap(),
+            ReplayStart::Offset(42)
+        );
+        assert_eq!(
+            "2021-09-01T12:00:00Z".parse::<ReplayStart>().unwrap(),
+            ReplayStart::Timestamp(Utc.ymd(2021, 9, 1).and_hms(12, 0, 0))
+        );
+        assert!("yesterday".parse::<ReplayStart>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_embedded() {
+        let storage = Storage::new(StorageOptions::default());
+        let raw = MaybeLarge::encode_embedded(&event());
+        assert_eq!(fetch(&storage, &raw).await, event());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_large() {
+        let (storage, paths) = stub_storage(Some(event().encode_to_vec())).await;
+        let raw = MaybeLarge::<proto::H256>::Large(proto::Large {
+            payload_path: "object".to_string(),
+            compression:  Compression::None.into(),
+        })
+        .encode_to_vec();
+        assert_eq!(fetch(&storage, &raw).await, event());
+        assert_eq!(*paths.lock().unwrap(), ["GET /bucket/topic/object"]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_compressed() {
+        let compressed = zstd::encode_all(event().encode_to_vec().as_slice(), 3).unwrap();
+        let (storage, _paths) = stub_storage(Some(compressed)).await;
+        let raw = MaybeLarge::<proto::H256>::Large(proto::Large {
+            payload_path: "object.zst".to_string(),
+            compression:  Compression::Zstd.into(),
+        })
+        .encode_to_vec();
+        assert_eq!(fetch(&storage, &raw).await, event());
     }
 }
@@ -0,0 +1,224 @@
+use core::time::Duration;
+
+use anyhow::{Context as _, Error as AnyError, Result as AnyResult};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use rdkafka::{
+    message::{Headers as _, OwnedHeaders},
+    producer::{FutureProducer, FutureRecord},
+    Message,
+};
+use tracing::error;
+
+use super::Options;
+
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+static DEAD_LETTERS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "kafka_dead_letters",
+        "Number of consumed messages skipped because they could not be decoded or fetched.",
+        &["topic"]
+    )
+    .unwrap()
+});
+
+/// Sink for consumed messages that can not be processed. Messages are logged
+/// and counted, and forwarded to the dead-letter topic if one is configured.
+pub(super) struct DeadLetter {
+    producer: Option<(FutureProducer, String)>,
+}
+
+impl DeadLetter {
+    pub(super) fn new(options: &Options) -> AnyResult<Self> {
+        let producer = options
+            .kafka_dead_letter_topic
+            .as_ref()
+            .map(|topic| {
+                let producer: FutureProducer = options
+                    .client_config(&[])
+                    .create()
+                    .context("Error creating Kafka dead-letter producer")?;
+                AnyResult::<_>::Ok((producer, topic.clone()))
+            })
+            .transpose()?;
+        Ok(Self { producer })
+    }
+
+    /// Skip `message`, forwarding it with the `error` and its origin in the
+    /// headers.
+    pub(super) async fn send(&self, message: &impl Message, error: &AnyError) -> AnyResult<()> {
+        error!(
+            topic = message.topic(),
+            partition = message.partition(),
+            offset = message.offset(),
+            "Skipping Kafka message: {:#}",
+            error
+        );
+        DEAD_LETTERS.with_label_values(&[message.topic()]).inc();
+        let (producer, topic) = match &self.producer {
+            Some(producer) => producer,
+            None => return Ok(()),
+        };
+        producer
+            .send(record(message, topic, error), QUEUE_TIMEOUT)
+            .await
+            .map_err(|(e, _)| e)
+            .context("Error sending Kafka dead-letter message")?;
+        Ok(())
+    }
+}
+
+/// Wrap `message` for the dead-letter `topic`. The key, payload and headers
+/// are kept as is, so the message can be replayed to its source topic.
+fn record<'a>(
+    message: &'a impl Message,
+    topic: &'a str,
+    error: &AnyError,
+) -> FutureRecord<'a, [u8], [u8]> {
+    let mut headers = OwnedHeaders::new();
+    if let Some(original) = message.headers() {
+        for (name, value) in (0..original.count()).filter_map(|index| original.get(index)) {
+            headers = headers.add(name, value);
+        }
+    }
+    let headers = headers
+        .add("error", &format!("{:#}", error))
+        .add("source_topic", message.topic())
+        .add("source_partition", &message.partition().to_string())
+        .add("source_offset", &message.offset().to_string());
+    let mut record = FutureRecord::to(topic)
+        .payload(message.payload().unwrap_or_default())
+        .headers(headers);
+    if let Some(key) = message.key() {
+        record = record.key(key);
+    }
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use chrono::Utc;
+    use futures::StreamExt as _;
+    use rdkafka::{
+        consumer::{Consumer as _, StreamConsumer},
+        message::{Headers, OwnedMessage, Timestamp},
+    };
+    use structopt::StructOpt as _;
+
+    use super::*;
+
+    fn header<'a>(headers: &'a impl Headers, name: &str) -> Option<&'a str> {
+        (0..headers.count())
+            .filter_map(|index| headers.get(index))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| std::str::from_utf8(value).unwrap())
+    }
+
+    fn message() -> OwnedMessage {
+        OwnedMessage::new(
+            Some(vec![0xff, 0x00]),
+            Some(b"order".to_vec()),
+            "events".to_string(),
+            Timestamp::NotAvailable,
+            3,
+            42,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_wrap() {
+        let message = message();
+        let error = anyhow!("invalid wire type").context("Error decoding message");
+        let record = record(&message, "dead_letters", &error);
+        assert_eq!(record.topic, "dead_letters");
+        assert_eq!(record.payload, Some(&[0xff, 0x00][..]));
+        assert_eq!(record.key, Some(&b"order"[..]));
+        let headers = record.headers.as_ref().unwrap();
+        assert_eq!(
+            header(headers, "error"),
+            Some("Error decoding message: invalid wire type")
+        );
+        assert_eq!(header(headers, "source_topic"), Some("events"));
+        assert_eq!(header(headers, "source_partition"), Some("3"));
+        assert_eq!(header(headers, "source_offset"), Some("42"));
+    }
+
+    #[ignore] // Requires Kafka on 127.0.0.1:9092
+    #[tokio::test]
+    async fn test_replay() {
+        let topic = format!("test-dead-letters-{}", Utc::now().timestamp_nanos());
+        let source = format!("{}-source", topic);
+        let options = Options::from_iter_safe(&["", "--kafka-dead-letter-topic", &topic]).unwrap();
+        let consume = |topic: &str| {
+            let consumer: StreamConsumer = options
+                .client_config(&[("group.id", topic), ("auto.offset.reset", "earliest")])
+                .create()
+                .unwrap();
+            consumer.subscribe(&[topic]).unwrap();
+            consumer
+        };
+        let original = OwnedMessage::new(
+            Some(vec![0xff, 0x00]),
+            Some(b"order".to_vec()),
+            source.clone(),
+            Timestamp::NotAvailable,
+            0,
+            42,
+            Some(OwnedHeaders::new().add("schema", "7")),
+        );
+
+        DeadLetter::new(&options)
+            .unwrap()
+            .send(&original, &anyhow!("object not found"))
+            .await
+            .unwrap();
+        let dead_letters = consume(&topic);
+        let dead_letter = dead_letters.stream().next().await.unwrap().unwrap();
+        let headers = dead_letter.headers().unwrap();
+        assert_eq!(header(headers, "error"), Some("object not found"));
+        assert_eq!(header(headers, "source_offset"), Some("42"));
+
+        // Send it back to its source topic without the dead-letter headers
+        let mut replay_headers = OwnedHeaders::new();
+        for (name, value) in (0..headers.count()).filter_map(|index| headers.get(index)) {
+            if !["error", "source_topic", "source_partition", "source_offset"].contains(&name) {
+                replay_headers = replay_headers.add(name, value);
+            }
+        }
+        let replay = FutureRecord::to(header(headers, "source_topic").unwrap())
+            .payload(dead_letter.payload().unwrap())
+            .key(dead_letter.key().unwrap())
+            .headers(replay_headers);
+        let producer: FutureProducer = options.client_config(&[]).create().unwrap();
+        producer
+            .send(replay, QUEUE_TIMEOUT)
+            .await
+            .map_err(|(e, _)| e)
+            .unwrap();
+
+        let replayed = consume(&source);
+        let replayed = replayed.stream().next().await.unwrap().unwrap();
+        assert_eq!(replayed.payload(), original.payload());
+        assert_eq!(replayed.key(), original.key());
+        let headers = replayed.headers().unwrap();
+        assert_eq!(headers.count(), 1);
+        assert_eq!(header(headers, "schema"), Some("7"));
+    }
+
+    #[tokio::test]
+    async fn test_skip_without_topic() {
+        let dead_letter = DeadLetter { producer: None };
+        let before = DEAD_LETTERS.with_label_values(&["events"]).get();
+        dead_letter
+            .send(&message(), &anyhow!("undecodable"))
+            .await
+            .unwrap();
+        assert_eq!(
+            DEAD_LETTERS.with_label_values(&["events"]).get(),
+            before + 1
+        );
+    }
+}
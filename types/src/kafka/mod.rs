@@ -1,18 +1,35 @@
 mod consumer;
+mod dead_letter;
 mod producer;
+mod registry;
 mod storage;
 
-use std::time::Duration;
+use core::str::FromStr;
+use std::{sync::Arc, time::Duration};
 
-use anyhow::{Context, Result as AnyResult};
+use anyhow::{anyhow, Context, Error as AnyError, Result as AnyResult};
+use chrono::Utc;
 use prost::Message;
-use rdkafka::{admin::AdminClient, metadata::MetadataTopic, ClientConfig};
+use rdkafka::{
+    admin::{AdminClient, AdminOptions, NewTopic, TopicReplication},
+    client::DefaultClientContext,
+    error::RDKafkaErrorCode,
+    metadata::MetadataTopic,
+    ClientConfig,
+};
 use structopt::StructOpt;
 use tokio::task::spawn_blocking;
 use tracing::{debug, info};
 
-use self::storage::Storage;
-pub use self::{consumer::KafkaConsumer, producer::KafkaProducer};
+pub use self::{
+    consumer::{KafkaConsumer, KafkaOffset, ReplayStart},
+    producer::KafkaProducer,
+    registry::ProtoFile,
+};
+use self::{
+    registry::{encode_header, Registry},
+    storage::Storage,
+};
 
 const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
 
@@ -29,12 +46,207 @@ pub struct Options {
     /// Threshold size in bytes where the Kafka message will be stored in AWS S3
     #[structopt(long, env, default_value = "500000")]
     kafka_large_message: usize,
+
+    /// Compress messages stored in AWS S3 with zstd at this level
+    #[structopt(long, env)]
+    kafka_large_zstd_level: Option<i32>,
+
+    /// Naming of messages stored in AWS S3, either 'timestamped' or 'content'.
+    /// Content-addressed messages are stored once, even if sent repeatedly.
+    #[structopt(long, env, default_value = "timestamped")]
+    kafka_large_naming: ObjectNaming,
+
+    /// Create missing topics with this many partitions when creating a
+    /// producer. Topics are not created if unset.
+    #[structopt(long, env)]
+    kafka_partitions: Option<i32>,
+
+    /// Replication factor of created topics
+    #[structopt(long, env, default_value = "1")]
+    kafka_replication: i32,
+
+    /// Transactional id prefix. If set, producers created with
+    /// [`Kafka::new_transactional_producer`] use Kafka transactions and the
+    /// topic name is appended to make the id unique per producer. It must be
+    /// stable across restarts to fence off stale instances.
+    #[structopt(long, env)]
+    kafka_transactional_id: Option<String>,
+
+    /// URL of a Confluent schema registry. If set, message schemas are
+    /// registered and messages use the Confluent wire format instead of the
+    /// `MaybeLarge` wrapper. Large messages are then not offloaded to S3.
+    #[structopt(long, env)]
+    kafka_schema_registry: Option<String>,
+
+    /// Consumer group id. Services reading the same topic need distinct group
+    /// ids to each receive all messages. Defaults to the executable name.
+    #[structopt(long, env)]
+    kafka_group_id: Option<String>,
+
+    /// Where to start consuming if the group has no committed offset, either
+    /// 'earliest' or 'latest'
+    #[structopt(long, env, default_value = "latest")]
+    kafka_offset_reset: String,
+
+    /// Topic to forward consumed messages to that can not be decoded or
+    /// fetched from AWS S3. Such messages are skipped, and only logged if
+    /// unset.
+    #[structopt(long, env)]
+    kafka_dead_letter_topic: Option<String>,
+
+    /// Offset commit mode, either 'auto' to periodically commit in the
+    /// background or 'manual' to commit only after a message is processed.
+    /// Consumers that do not report processing commit on delivery instead.
+    #[structopt(long, env, default_value = "auto")]
+    kafka_commit: OffsetCommit,
+
+    /// Protocol used to communicate with brokers, one of 'plaintext', 'ssl',
+    /// 'sasl_plaintext' or 'sasl_ssl'
+    #[structopt(long, env)]
+    kafka_security_protocol: Option<String>,
+
+    /// SASL mechanism, for example 'PLAIN', 'SCRAM-SHA-512' or
+    /// 'AWS_MSK_IAM'
+    #[structopt(long, env)]
+    kafka_sasl_mechanism: Option<String>,
+
+    /// SASL username
+    #[structopt(long, env)]
+    kafka_sasl_username: Option<String>,
+
+    /// SASL password
+    #[structopt(long, env, hide_env_values = true)]
+    kafka_sasl_password: Option<String>,
+
+    /// Path to the CA certificate(s) for verifying the broker's key
+    #[structopt(long, env)]
+    kafka_ssl_ca_location: Option<String>,
+
+    /// Path to the client's public key (PEM) used for authentication
+    #[structopt(long, env)]
+    kafka_ssl_certificate_location: Option<String>,
+
+    /// Path to the client's private key (PEM) used for authentication
+    #[structopt(long, env)]
+    kafka_ssl_key_location: Option<String>,
+
+    /// Private key passphrase
+    #[structopt(long, env, hide_env_values = true)]
+    kafka_ssl_key_password: Option<String>,
+
+    /// Additional librdkafka configuration as `key=value`, overriding all
+    /// other settings. Separated by ';' in the environment variable.
+    /// See <https://github.com/edenhill/librdkafka/blob/master/CONFIGURATION.md>
+    #[structopt(
+        long,
+        env,
+        value_delimiter = ";",
+        parse(try_from_str = parse_key_value)
+    )]
+    kafka_config: Vec<(String, String)>,
+}
+
+impl Options {
+    /// Create a client config with the brokers, security options and the
+    /// client specific `settings`. The `--kafka-config` values are applied
+    /// last.
+    fn client_config(&self, settings: &[(&str, &str)]) -> ClientConfig {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", &self.kafka_brokers);
+        let security = [
+            ("security.protocol", &self.kafka_security_protocol),
+            ("sasl.mechanism", &self.kafka_sasl_mechanism),
+            ("sasl.username", &self.kafka_sasl_username),
+            ("sasl.password", &self.kafka_sasl_password),
+            ("ssl.ca.location", &self.kafka_ssl_ca_location),
+            (
+                "ssl.certificate.location",
+                &self.kafka_ssl_certificate_location,
+            ),
+            ("ssl.key.location", &self.kafka_ssl_key_location),
+            ("ssl.key.password", &self.kafka_ssl_key_password),
+        ];
+        for (key, value) in security {
+            if let Some(value) = value {
+                config.set(key, value);
+            }
+        }
+        for (key, value) in settings {
+            config.set(*key, *value);
+        }
+        for (key, value) in &self.kafka_config {
+            config.set(key, value);
+        }
+        config
+    }
+}
+
+fn parse_key_value(s: &str) -> AnyResult<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected key=value, got: {}", s))?;
+    Ok((key.trim().to_string(), value.trim().to_string()))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectNaming {
+    Timestamped,
+    ContentAddressed,
+}
+
+impl FromStr for ObjectNaming {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "timestamped" => Ok(Self::Timestamped),
+            "content" => Ok(Self::ContentAddressed),
+            _ => Err(anyhow!("Invalid object naming: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OffsetCommit {
+    Auto,
+    Manual,
+}
+
+impl FromStr for OffsetCommit {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "manual" => Ok(Self::Manual),
+            _ => Err(anyhow!("Invalid offset commit mode: {}", s)),
+        }
+    }
+}
+
+/// Delete large message objects of `topic` older than `retention`. Returns
+/// the number of deleted (in a dry run: expired) objects.
+///
+/// Consumers lagging more than `retention` behind will fail to fetch these
+/// messages, so pick it above the topic's own retention.
+pub async fn cleanup_large_messages(
+    options: &Options,
+    topic: &str,
+    retention: Duration,
+    dry_run: bool,
+) -> AnyResult<usize> {
+    let storage = Storage::new(options.storage.clone());
+    let before = Utc::now() - chrono::Duration::from_std(retention)?;
+    storage
+        .delete_expired(format!("{}/", topic), before, dry_run)
+        .await
 }
 
 #[derive(Clone)]
 pub struct Kafka {
-    options: Options,
-    storage: Storage,
+    options:  Options,
+    storage:  Storage,
+    registry: Option<Arc<Registry>>,
 }
 
 impl Kafka {
@@ -42,15 +254,23 @@ impl Kafka {
         // Create storage
         let storage = storage::Storage::new(options.storage.clone());
 
+        // Create schema registry client
+        let registry = options
+            .kafka_schema_registry
+            .as_deref()
+            .map(Registry::new)
+            .transpose()?
+            .map(Arc::new);
+
         // Test Kafka client config
         spawn_blocking({
             let brokers = options.kafka_brokers.clone();
+            let config = options.client_config(&[]);
             move || {
                 info!("Connecting to Kafka at {}", &brokers);
 
                 // See <https://docs.confluent.io/platform/current/clients/librdkafka/html/md_CONFIGURATION.html>
-                let admin: AdminClient<_> = ClientConfig::new()
-                    .set("bootstrap.servers", &brokers)
+                let admin: AdminClient<_> = config
                     .create()
                     .with_context(|| format!("Error connecting to Kafka {}", &brokers))?;
 
@@ -85,15 +305,46 @@ impl Kafka {
         })
         .await??;
 
-        Ok(Self { options, storage })
+        Ok(Self {
+            options,
+            storage,
+            registry,
+        })
     }
 
     /// Create a new [`KafkaProducer`] for a given topic and type.
-    pub async fn new_producer<T: Message + Default + Send + Sync>(
+    pub async fn new_producer<T: Message + Default + ProtoFile + Send + Sync>(
+        &self,
+        topic: &str,
+    ) -> AnyResult<KafkaProducer<T>> {
+        self.create_producer(topic, false).await
+    }
+
+    /// Create a new [`KafkaProducer`] that sends all messages in transactions
+    /// if `--kafka-transactional-id` is set, see
+    /// [`KafkaProducer::is_transactional`].
+    pub async fn new_transactional_producer<T: Message + Default + ProtoFile + Send + Sync>(
+        &self,
+        topic: &str,
+    ) -> AnyResult<KafkaProducer<T>> {
+        self.create_producer(topic, true).await
+    }
+
+    async fn create_producer<T: Message + Default + ProtoFile + Send + Sync>(
         &self,
         topic: &str,
+        transactional: bool,
     ) -> AnyResult<KafkaProducer<T>> {
-        KafkaProducer::<T>::new(self, topic)
+        if let Some(partitions) = self.options.kafka_partitions {
+            self.create_topic(topic, partitions).await?;
+        }
+        let mut producer = KafkaProducer::<T>::new(self, topic, transactional)?;
+        if let Some(registry) = &self.registry {
+            let subject = format!("{}-value", topic);
+            let schema_id = registry.register(&subject, T::PROTO_FILE).await?;
+            producer.wire_header = Some(encode_header(schema_id, T::MESSAGE_INDEXES));
+        }
+        Ok(producer)
     }
 
     /// Create a new [`KafkaConsumer`] for a given topic and type.
@@ -103,4 +354,112 @@ impl Kafka {
     ) -> AnyResult<KafkaConsumer<T>> {
         KafkaConsumer::<T>::new(self, topic)
     }
+
+    /// Create a [`KafkaConsumer`] that reads `topic` from `start` up to its
+    /// current end, see [`KafkaConsumer::new_replay`].
+    pub async fn new_replay<T: Message + Default + Send + Sync>(
+        &self,
+        topic: &str,
+        start: ReplayStart,
+    ) -> AnyResult<KafkaConsumer<T>> {
+        KafkaConsumer::<T>::new_replay(self, topic, start).await
+    }
+
+    /// Create a topic if it does not exist yet.
+    async fn create_topic(&self, topic: &str, partitions: i32) -> AnyResult<()> {
+        let admin: AdminClient<DefaultClientContext> = self
+            .options
+            .client_config(&[])
+            .create()
+            .context("Error creating Kafka admin client")?;
+        let new_topic = NewTopic::new(
+            topic,
+            partitions,
+            TopicReplication::Fixed(self.options.kafka_replication),
+        );
+        let results = admin
+            .create_topics(&[new_topic], &AdminOptions::new())
+            .await
+            .with_context(|| format!("Error creating Kafka topic {}", topic))?;
+        for result in results {
+            match result {
+                Ok(topic) => {
+                    info!(
+                        "Created Kafka topic {} with {} partitions",
+                        topic, partitions
+                    )
+                }
+                Err((topic, RDKafkaErrorCode::TopicAlreadyExists)) => {
+                    debug!("Kafka topic {} already exists", topic);
+                }
+                Err((topic, code)) => {
+                    return Err(anyhow!("Error creating Kafka topic {}: {}", topic, code));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rdkafka::producer::BaseProducer;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_key_value() {
+        assert_eq!(
+            parse_key_value(" sasl.kerberos.service.name = kafka").unwrap(),
+            (
+                "sasl.kerberos.service.name".to_string(),
+                "kafka".to_string()
+            )
+        );
+        // Only the first '=' separates
+        assert_eq!(
+            parse_key_value("sasl.oauthbearer.config=principal=admin").unwrap(),
+            (
+                "sasl.oauthbearer.config".to_string(),
+                "principal=admin".to_string()
+            )
+        );
+        assert!(parse_key_value("linger.ms").is_err());
+    }
+
+    #[test]
+    fn test_client_config() {
+        let options = Options::from_iter_safe(&[
+            "",
+            "--kafka-brokers",
+            "broker:9093",
+            "--kafka-security-protocol",
+            "sasl_ssl",
+            "--kafka-sasl-mechanism",
+            "SCRAM-SHA-512",
+            "--kafka-sasl-username",
+            "user",
+            "--kafka-sasl-password",
+            "secret",
+            "--kafka-config",
+            "linger.ms=10",
+            "--kafka-config",
+            "sasl.username=override",
+        ])
+        .unwrap();
+        let config = options.client_config(&[("linger.ms", "5"), ("acks", "all")]);
+        assert_eq!(config.get("bootstrap.servers"), Some("broker:9093"));
+        assert_eq!(config.get("security.protocol"), Some("sasl_ssl"));
+        assert_eq!(config.get("sasl.mechanism"), Some("SCRAM-SHA-512"));
+        assert_eq!(config.get("sasl.password"), Some("secret"));
+        assert_eq!(config.get("ssl.ca.location"), None);
+        assert_eq!(config.get("acks"), Some("all"));
+        // `--kafka-config` overrides everything else
+        assert_eq!(config.get("linger.ms"), Some("10"));
+        assert_eq!(config.get("sasl.username"), Some("override"));
+
+        // Fails if librdkafka is built without SSL support
+        let producer: Result<BaseProducer, _> = config.create();
+        assert!(producer.is_ok());
+    }
 }
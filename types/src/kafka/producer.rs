@@ -7,28 +7,33 @@ use std::marker::PhantomData;
 use anyhow::{Context as _, Result as AnyResult};
 use chrono::{DateTime, SecondsFormat, Utc};
 use prost::Message;
-use rdkafka::{
-    producer::{FutureProducer, FutureRecord},
-    ClientConfig,
-};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer as _};
 use sha3::{Digest as _, Sha3_256};
+use tokio::task::spawn_blocking;
 use tracing::debug;
 
-use super::Kafka;
-use crate::proto;
+use super::{Kafka, ObjectNaming};
+use crate::{proto, proto::large::Compression, MaybeLarge};
 
 const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
 
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Kafka messages with the same key go to the same partition and are therefore
-/// guaranteed to be delivered in order.
+/// guaranteed to be delivered in order. This key is used by
+/// [`KafkaProducer::send`], use [`KafkaProducer::send_keyed`] to spread
+/// messages over partitions.
 const PARTITION_KEY: &str = "order_watcher_events";
 
 #[derive(Clone)]
 pub struct KafkaProducer<T: Message + Default + Send + Sync> {
-    client:   Kafka,
-    producer: FutureProducer,
-    topic:    String,
-    phantom:  PhantomData<T>,
+    client:                 Kafka,
+    producer:               FutureProducer,
+    topic:                  String,
+    transactional:          bool,
+    /// Confluent wire format header, if a schema registry is used
+    pub(super) wire_header: Option<Vec<u8>>,
+    phantom:                PhantomData<T>,
 }
 
 impl<T: Message + Default + Send + Sync> Debug for KafkaProducer<T> {
@@ -38,41 +43,67 @@ impl<T: Message + Default + Send + Sync> Debug for KafkaProducer<T> {
 }
 
 impl<T: Message + Default + Send + Sync> KafkaProducer<T> {
-    pub fn new(client: &Kafka, topic: &str) -> AnyResult<Self> {
+    /// Create a producer for `topic`. If `transactional` and a transactional
+    /// id prefix is configured, all messages must be sent in transactions.
+    pub fn new(client: &Kafka, topic: &str, transactional: bool) -> AnyResult<Self> {
         let client = client.clone();
         let topic = topic.to_string();
-        let producer: FutureProducer = ClientConfig::new()
-            .set("bootstrap.servers", &client.options.kafka_brokers)
+        let transactional_id = client
+            .options
+            .kafka_transactional_id
+            .as_ref()
+            .filter(|_| transactional)
+            .map(|prefix| format!("{}-{}", prefix, topic));
+        let settings = transactional_id
+            .as_deref()
+            .map(|id| ("transactional.id", id))
+            .into_iter()
+            .collect::<Vec<_>>();
+        let producer: FutureProducer = client
+            .options
+            .client_config(&settings)
             .create()
             .context("Error creating Kafka Producer")?;
+        let transactional = transactional_id.is_some();
+        if transactional {
+            // Blocks, but only once on startup.
+            producer
+                .init_transactions(TRANSACTION_TIMEOUT)
+                .context("Error initializing Kafka transactions")?;
+        }
         Ok(Self {
             client,
             producer,
             topic,
+            transactional,
+            wire_header: None,
             phantom: PhantomData,
         })
     }
 
-    /// TODO: Reduce the allocations and copies / re-encodings of data.
     pub async fn send(&self, message: &T) -> AnyResult<()> {
+        self.send_keyed(PARTITION_KEY, message).await
+    }
+
+    /// Send a message with a partition key. Messages with the same key are
+    /// delivered in order.
+    ///
+    /// TODO: Reduce the allocations and copies / re-encodings of data.
+    pub async fn send_keyed(&self, key: &str, message: &T) -> AnyResult<()> {
         // Encode message
-        let message = message.encode_to_vec();
-
-        // If the message is to large, upload to object storage
-        let message = if message.len() < self.client.options.kafka_large_message {
-            let wrapped = proto::MaybeLarge {
-                maybe_large: Some(proto::maybe_large::MaybeLarge::Embedded(message)),
-            };
-            wrapped.encode_to_vec()
+        let message = if let Some(header) = &self.wire_header {
+            let mut wire = header.clone();
+            message.encode(&mut wire)?;
+            wire
         } else {
-            self.upload_message(message).await?
+            self.wrap_message(message).await?
         };
 
         let record = FutureRecord {
             topic:     &self.topic,
             partition: None,
             payload:   Some(&message),
-            key:       Some(PARTITION_KEY),
+            key:       Some(key),
             timestamp: Some(Utc::now().timestamp()),
             headers:   None,
         };
@@ -89,20 +120,83 @@ impl<T: Message + Default + Send + Sync> KafkaProducer<T> {
         Ok(())
     }
 
+    /// Whether the producer uses transactions. If so, all messages must be
+    /// sent within a transaction.
+    pub fn is_transactional(&self) -> bool {
+        self.transactional
+    }
+
+    pub fn begin_transaction(&self) -> AnyResult<()> {
+        self.producer
+            .begin_transaction()
+            .context("Error beginning Kafka transaction")
+    }
+
+    /// Commit the current transaction, flushing all messages sent within it.
+    pub async fn commit_transaction(&self) -> AnyResult<()> {
+        let producer = self.producer.clone();
+        spawn_blocking(move || producer.commit_transaction(TRANSACTION_TIMEOUT))
+            .await?
+            .context("Error committing Kafka transaction")
+    }
+
+    pub async fn abort_transaction(&self) -> AnyResult<()> {
+        let producer = self.producer.clone();
+        spawn_blocking(move || producer.abort_transaction(TRANSACTION_TIMEOUT))
+            .await?
+            .context("Error aborting Kafka transaction")
+    }
+
+    /// Encode a message wrapped in [`MaybeLarge`], uploading it to object
+    /// storage if it is too large.
+    async fn wrap_message(&self, message: &T) -> AnyResult<Vec<u8>> {
+        if message.encoded_len() < self.client.options.kafka_large_message {
+            Ok(MaybeLarge::encode_embedded(message))
+        } else {
+            self.upload_message(message.encode_to_vec()).await
+        }
+    }
+
     /// Upload encoded message and return encoded pointer message
     async fn upload_message(&self, message: Vec<u8>) -> AnyResult<Vec<u8>> {
-        let name = object_name(Utc::now(), &message);
+        let mut name = match self.client.options.kafka_large_naming {
+            ObjectNaming::Timestamped => object_name(Utc::now(), &message),
+            ObjectNaming::ContentAddressed => content_name(&message),
+        };
+
+        // Compress
+        let (data, compression) = match self.client.options.kafka_large_zstd_level {
+            Some(level) => {
+                name.push_str(".zst");
+                let compressed = zstd::encode_all(message.as_slice(), level)
+                    .context("Error compressing message")?;
+                (compressed, Compression::Zstd)
+            }
+            None => (message, Compression::None),
+        };
 
-        // Upload with unique name
+        // Upload with unique name. Content-addressed objects are only uploaded
+        // once, repeats refresh their modification time for retention.
         let topic_prefixed = format!("{}/{}", self.topic, &name);
-        self.client.storage.upload(topic_prefixed, message).await?;
+        match self.client.options.kafka_large_naming {
+            ObjectNaming::Timestamped => self.client.storage.upload(topic_prefixed, data).await?,
+            ObjectNaming::ContentAddressed => {
+                let uploaded = self
+                    .client
+                    .storage
+                    .upload_once(topic_prefixed, data)
+                    .await?;
+                if !uploaded {
+                    debug!(%name, "Large message already stored");
+                }
+            }
+        }
 
         // Create a Large message variant
-        let pointer = proto::MaybeLarge {
-            maybe_large: Some(proto::maybe_large::MaybeLarge::Large(proto::Large {
-                payload_path: name,
-            })),
-        };
+        let pointer = MaybeLarge::<T>::Large(proto::Large {
+            payload_path: name,
+            compression:  compression.into(),
+        });
 
         let message = pointer.encode_to_vec();
         Ok(message)
@@ -117,28 +211,94 @@ impl<T: Message + Default + Send + Sync> KafkaProducer<T> {
 /// <year>/<iso date>/<iso datetime>-<sha3 content hash>
 /// ```
 fn object_name(when: DateTime<Utc>, data: &[u8]) -> String {
-    // Compute blob hash
-    let hash = {
-        let mut hasher = Sha3_256::new();
-        hasher.update(data);
-        let hash = hasher.finalize();
-        hex::encode(&hash)
-    };
-
     format!(
         "{}/{}/{}-{}",
         when.format("%Y"),
         when.format("%F"),
         when.to_rfc3339_opts(SecondsFormat::Secs, true),
-        hash
+        content_hash(data)
     )
 }
 
+/// Creates a name determined only by the content, so identical data is stored
+/// once.
+///
+/// The naming scheme is:
+///
+/// ```text
+/// sha3/<sha3 content hash>
+/// ```
+fn content_name(data: &[u8]) -> String {
+    format!("sha3/{}", content_hash(data))
+}
+
+fn content_hash(data: &[u8]) -> String {
+    let mut hasher = Sha3_256::new();
+    hasher.update(data);
+    let hash = hasher.finalize();
+    hex::encode(hash)
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::TimeZone as _;
+    use futures::StreamExt as _;
+    use rdkafka::{
+        consumer::{Consumer as _, StreamConsumer},
+        Message as _,
+    };
+    use structopt::StructOpt as _;
 
     use super::*;
+    use crate::{kafka::storage::Storage, IntoProto, Options};
+
+    fn client(args: &[&str]) -> Kafka {
+        let options = Options::from_iter_safe(args).unwrap();
+        Kafka {
+            storage: Storage::new(options.storage.clone()),
+            options,
+            registry: None,
+        }
+    }
+
+    #[test]
+    fn test_transactions_opt_in() {
+        let kafka = client(&["", "--kafka-transactional-id", "test"]);
+        let producer = KafkaProducer::<proto::H256>::new(&kafka, "topic", false).unwrap();
+        assert!(!producer.is_transactional());
+    }
+
+    #[ignore] // Requires Kafka on 127.0.0.1:9092
+    #[tokio::test]
+    async fn test_transactions() {
+        let topic = format!("test-transactions-{}", Utc::now().timestamp_nanos());
+        let kafka = client(&["", "--kafka-transactional-id", &topic]);
+        let producer = KafkaProducer::<proto::H256>::new(&kafka, &topic, true).unwrap();
+        assert!(producer.is_transactional());
+        let message = |byte| web3::types::H256::repeat_byte(byte).into_proto();
+
+        producer.begin_transaction().unwrap();
+        producer.send(&message(1)).await.unwrap();
+        producer.abort_transaction().await.unwrap();
+        producer.begin_transaction().unwrap();
+        producer.send(&message(2)).await.unwrap();
+        producer.commit_transaction().await.unwrap();
+
+        // Only the committed message is visible
+        let consumer: StreamConsumer = kafka
+            .options
+            .client_config(&[
+                ("group.id", topic.as_str()),
+                ("auto.offset.reset", "earliest"),
+                ("isolation.level", "read_committed"),
+            ])
+            .create()
+            .unwrap();
+        consumer.subscribe(&[&topic]).unwrap();
+        let received = consumer.stream().next().await.unwrap().unwrap();
+        let received = MaybeLarge::<proto::H256>::decode(received.payload().unwrap()).unwrap();
+        assert_eq!(received, MaybeLarge::Embedded(message(2)));
+    }
 
     #[test]
     fn test_blob_name() {
@@ -150,4 +310,13 @@ mod tests {
              11Z-1af17a664e3fa8e419b8ba05c2a173169df76162a5a286e0c405b460d478f7ef"
         );
     }
+
+    #[test]
+    fn test_content_name() {
+        let blob = b"Hello, World!";
+        assert_eq!(
+            content_name(blob),
+            "sha3/1af17a664e3fa8e419b8ba05c2a173169df76162a5a286e0c405b460d478f7ef"
+        );
+    }
 }
@@ -0,0 +1,268 @@
+//! Confluent schema registry client and wire format.
+//!
+//! See <https://docs.confluent.io/platform/current/schema-registry/serdes-develop/index.html#wire-format>
+
+use core::convert::TryInto;
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::{anyhow, Context as _, Result as AnyResult};
+use futures::{future::BoxFuture, FutureExt as _};
+use prost::encoding::{decode_varint, encode_varint};
+use reqwest::{header::CONTENT_TYPE, Client, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::info;
+
+include!(concat!(env!("OUT_DIR"), "/proto_files.rs"));
+
+const MAGIC_BYTE: u8 = 0;
+
+const SCHEMA_CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+
+/// Message types that can be registered with a schema registry.
+pub trait ProtoFile {
+    /// Path of the defining `.proto` file, relative to `types/protobuf`
+    const PROTO_FILE: &'static str;
+
+    /// Path of indices to the message type within the file
+    const MESSAGE_INDEXES: &'static [i32] = &[0];
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SchemaRequest<'a> {
+    schema_type: &'static str,
+    schema:      &'a str,
+    references:  Vec<Reference>,
+}
+
+#[derive(Serialize)]
+struct Reference {
+    name:    String,
+    subject: String,
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct IdResponse {
+    id: u32,
+}
+
+#[derive(Deserialize)]
+struct VersionResponse {
+    version: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Registered {
+    id:      u32,
+    version: u32,
+}
+
+pub struct Registry {
+    url:        Url,
+    client:     Client,
+    registered: Mutex<HashMap<String, Registered>>,
+}
+
+impl Registry {
+    pub fn new(url: &str) -> AnyResult<Self> {
+        Ok(Self {
+            url:        Url::parse(url).context("Invalid schema registry URL")?,
+            client:     Client::new(),
+            registered: Mutex::default(),
+        })
+    }
+
+    /// Register `file` and its imports, returning the schema id for `subject`.
+    pub async fn register(&self, subject: &str, file: &str) -> AnyResult<u32> {
+        let registered = self
+            .register_file(subject.to_string(), file.to_string())
+            .await?;
+        Ok(registered.id)
+    }
+
+    fn register_file(&self, subject: String, file: String) -> BoxFuture<'_, AnyResult<Registered>> {
+        async move {
+            let cached = self.registered.lock().unwrap().get(&subject).copied();
+            if let Some(registered) = cached {
+                return Ok(registered);
+            }
+            let schema = proto_file(&file)?;
+
+            // Imports are registered with their path as subject
+            let mut references = Vec::new();
+            for import in imports(schema) {
+                // Well-known types are built into the registry
+                if import.starts_with("google/protobuf/") {
+                    continue;
+                }
+                let registered = self
+                    .register_file(import.to_string(), import.to_string())
+                    .await?;
+                references.push(Reference {
+                    name:    import.to_string(),
+                    subject: import.to_string(),
+                    version: registered.version,
+                });
+            }
+            let request = SchemaRequest {
+                schema_type: "PROTOBUF",
+                schema,
+                references,
+            };
+            let IdResponse { id } = self
+                .post(&["subjects", &subject, "versions"], &request)
+                .await?;
+
+            // Look up the version, the schema may have been registered before
+            let VersionResponse { version } = self.post(&["subjects", &subject], &request).await?;
+
+            info!(%subject, id, version, "Registered schema");
+            let registered = Registered { id, version };
+            self.registered.lock().unwrap().insert(subject, registered);
+            Ok(registered)
+        }
+        .boxed()
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        path: &[&str],
+        request: &SchemaRequest<'_>,
+    ) -> AnyResult<T> {
+        // Subjects can contain `/`, which are escaped as path segments
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Invalid schema registry URL"))?
+            .pop_if_empty()
+            .extend(path);
+        let response = self
+            .client
+            .post(url.clone())
+            .header(CONTENT_TYPE, SCHEMA_CONTENT_TYPE)
+            .json(request)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("Error requesting schema registry {}", url))?;
+        Ok(response.json().await?)
+    }
+}
+
+/// Encode the header prefixing messages in the wire format.
+pub fn encode_header(schema_id: u32, indexes: &[i32]) -> Vec<u8> {
+    let mut header = vec![MAGIC_BYTE];
+    header.extend_from_slice(&schema_id.to_be_bytes());
+    if indexes == [0] {
+        // The common case of the first message is encoded as a single zero
+        header.push(0);
+    } else {
+        encode_varint(zigzag(indexes.len() as i32), &mut header);
+        for index in indexes {
+            encode_varint(zigzag(*index), &mut header);
+        }
+    }
+    header
+}
+
+/// Strip the wire format header, returning the schema id and the message.
+pub fn decode_header(data: &[u8]) -> AnyResult<(u32, &[u8])> {
+    if data.len() < 5 || data[0] != MAGIC_BYTE {
+        return Err(anyhow!("Invalid wire format header"));
+    }
+    let schema_id = u32::from_be_bytes(data[1..5].try_into()?);
+    let mut data = &data[5..];
+    let count = decode_varint(&mut data)? >> 1;
+    for _ in 0..count {
+        let _index = decode_varint(&mut data)?;
+    }
+    Ok((schema_id, data))
+}
+
+fn zigzag(n: i32) -> u64 {
+    u64::from(((n << 1) ^ (n >> 31)) as u32)
+}
+
+fn proto_file(file: &str) -> AnyResult<&'static str> {
+    PROTO_FILES
+        .iter()
+        .find(|(name, _)| *name == file)
+        .map(|(_, schema)| *schema)
+        .ok_or_else(|| anyhow!("Unknown proto file {}", file))
+}
+
+fn imports(schema: &str) -> impl Iterator<Item = &str> {
+    schema.lines().filter_map(|line| {
+        line.trim()
+            .strip_prefix("import ")?
+            .trim()
+            .strip_suffix(';')?
+            .trim()
+            .strip_prefix('"')?
+            .strip_suffix('"')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, sync::Arc};
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_wire_header() {
+        let header = encode_header(42, &[0]);
+        assert_eq!(header, [0, 0, 0, 0, 42, 0]);
+        let message = [header, vec![1, 2, 3]].concat();
+        assert_eq!(decode_header(&message).unwrap(), (42, &[1, 2, 3][..]));
+
+        let message = [encode_header(7, &[1, 2]), vec![4]].concat();
+        assert_eq!(decode_header(&message).unwrap(), (7, &[4][..]));
+    }
+
+    #[test]
+    fn test_imports() {
+        let schema = proto_file("zeroex/order_event.proto").unwrap();
+        assert!(imports(schema).any(|import| import == "zeroex/limit_order.proto"));
+    }
+
+    #[tokio::test]
+    async fn test_register() {
+        // Stub registry accepting all schemas
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let make_service = make_service_fn({
+            let paths = paths.clone();
+            move |_| {
+                let paths = paths.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                        paths.lock().unwrap().push(request.uri().path().to_string());
+                        async {
+                            Ok::<_, Infallible>(Response::new(Body::from(
+                                r#"{"id": 7, "version": 1}"#,
+                            )))
+                        }
+                    }))
+                }
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let registry = Registry::new(&url).unwrap();
+        let id = registry
+            .register("order_events-value", "zeroex/order_event.proto")
+            .await
+            .unwrap();
+        assert_eq!(id, 7);
+        let paths = paths.lock().unwrap();
+        assert!(paths.contains(&"/subjects/order_events-value/versions".to_string()));
+        assert!(paths.contains(&"/subjects/web3%2Fh256.proto/versions".to_string()));
+    }
+}
@@ -1,18 +1,28 @@
+use anyhow::{anyhow, Context as _, Result as AnyResult};
+use chrono::{DateTime, Utc};
 use futures::stream::Stream;
 use rusoto_core::{ByteStream, Region, RusotoError};
-use rusoto_s3::{GetObjectError, GetObjectRequest, PutObjectError, PutObjectRequest, S3Client, S3};
+use rusoto_s3::{
+    CopyObjectRequest, Delete, DeleteObjectsRequest, GetObjectError, GetObjectRequest,
+    HeadObjectError, HeadObjectRequest, ListObjectsV2Request, ObjectIdentifier, PutObjectError,
+    PutObjectRequest, S3Client, S3,
+};
 use structopt::StructOpt;
 use tokio::io::AsyncReadExt;
+use tracing::info;
+
+/// Maximum number of keys S3 accepts in a single delete request.
+const DELETE_BATCH: usize = 1000;
 
 #[derive(Clone, StructOpt, Debug, PartialEq)]
 pub struct Options {
     /// AWS S3 Storage region for large kafka events
     #[structopt(long, env, default_value = "us-east-1")]
-    kafka_region: Region,
+    pub(super) kafka_region: Region,
 
     /// AWS S3 Storage bucket for large kafka events
     #[structopt(long, env, default_value = "0x-kafka-large-events")]
-    kafka_bucket: String,
+    pub(super) kafka_bucket: String,
 }
 
 impl Default for Options {
@@ -30,9 +40,21 @@ pub struct Storage {
 impl Storage {
     pub fn new(options: Options) -> Self {
         let client = S3Client::new(options.kafka_region.clone());
+        Self { options, client }
+    }
 
-        // TODO: Test config
-
+    /// Create a client with fixed credentials instead of the ones from the
+    /// environment.
+    #[cfg(test)]
+    pub(super) fn with_credentials(options: Options, access_key: &str, secret_key: &str) -> Self {
+        let client = S3Client::new_with(
+            rusoto_core::HttpClient::new().unwrap(),
+            rusoto_core::credential::StaticProvider::new_minimal(
+                access_key.to_string(),
+                secret_key.to_string(),
+            ),
+            options.kafka_region.clone(),
+        );
         Self { options, client }
     }
 
@@ -54,6 +76,46 @@ impl Storage {
         Ok(())
     }
 
+    /// Upload unless an object with `key` exists. Existing objects are copied
+    /// onto themselves instead, which refreshes their modification time for
+    /// [`Self::delete_expired`] without sending the data again. Returns
+    /// whether the data was uploaded.
+    pub async fn upload_once(&self, key: String, data: Vec<u8>) -> AnyResult<bool> {
+        if !self.exists(key.clone()).await? {
+            self.upload(key, data).await?;
+            return Ok(true);
+        }
+        self.client
+            .copy_object(CopyObjectRequest {
+                bucket: self.options.kafka_bucket.clone(),
+                copy_source: format!("{}/{}", self.options.kafka_bucket, key),
+                key,
+                metadata_directive: Some("REPLACE".to_string()),
+                ..CopyObjectRequest::default()
+            })
+            .await
+            .context("Error refreshing object")?;
+        Ok(false)
+    }
+
+    pub async fn exists(&self, key: String) -> AnyResult<bool> {
+        let result = self
+            .client
+            .head_object(HeadObjectRequest {
+                bucket: self.options.kafka_bucket.clone(),
+                key,
+                ..HeadObjectRequest::default()
+            })
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            // Responses to HEAD have no body to parse the error from
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(false),
+            Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => Ok(false),
+            Err(error) => Err(error).context("Error checking object"),
+        }
+    }
+
     pub async fn download(&self, key: String) -> Result<Vec<u8>, RusotoError<GetObjectError>> {
         let output = self
             .client
@@ -72,14 +134,174 @@ impl Storage {
         assert_eq!(read, data.len());
         Ok(data)
     }
+
+    /// Delete objects under `prefix` last modified before `before`. Returns
+    /// the number of (in a dry run: matching) objects.
+    pub async fn delete_expired(
+        &self,
+        prefix: String,
+        before: DateTime<Utc>,
+        dry_run: bool,
+    ) -> AnyResult<usize> {
+        let mut expired = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let output = self
+                .client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.options.kafka_bucket.clone(),
+                    prefix: Some(prefix.clone()),
+                    continuation_token,
+                    ..ListObjectsV2Request::default()
+                })
+                .await
+                .context("Error listing objects")?;
+            for object in output.contents.unwrap_or_default() {
+                let (key, last_modified) = match (object.key, object.last_modified) {
+                    (Some(key), Some(last_modified)) => (key, last_modified),
+                    _ => continue,
+                };
+                let last_modified = DateTime::parse_from_rfc3339(&last_modified)
+                    .with_context(|| format!("Invalid modification time for {}", key))?;
+                if last_modified < before {
+                    expired.push(key);
+                }
+            }
+            continuation_token = output.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        if dry_run {
+            for key in &expired {
+                info!(%key, "Would delete expired object");
+            }
+            return Ok(expired.len());
+        }
+        for batch in expired.chunks(DELETE_BATCH) {
+            let output = self
+                .client
+                .delete_objects(DeleteObjectsRequest {
+                    bucket: self.options.kafka_bucket.clone(),
+                    delete: Delete {
+                        objects: batch
+                            .iter()
+                            .map(|key| {
+                                ObjectIdentifier {
+                                    key:        key.clone(),
+                                    version_id: None,
+                                }
+                            })
+                            .collect(),
+                        quiet:   Some(true),
+                    },
+                    ..DeleteObjectsRequest::default()
+                })
+                .await
+                .context("Error deleting objects")?;
+            if let Some(error) = output.errors.unwrap_or_default().into_iter().next() {
+                return Err(anyhow!(
+                    "Error deleting {}: {}",
+                    error.key.unwrap_or_default(),
+                    error.message.unwrap_or_default()
+                ));
+            }
+            info!(count = batch.len(), "Deleted expired objects");
+        }
+        Ok(expired.len())
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(super) mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Method, Request, Response, Server, StatusCode,
+    };
     use tracing_test::traced_test;
 
     use super::*;
 
+    /// Serve `body` for any object from a stub S3, or no objects if `None`,
+    /// returning the storage and the requests as `<method> <path>`, marking
+    /// copies.
+    pub async fn stub_storage(body: Option<Vec<u8>>) -> (Storage, Arc<Mutex<Vec<String>>>) {
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let make_service = make_service_fn({
+            let paths = paths.clone();
+            move |_| {
+                let paths = paths.clone();
+                let body = body.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                        let copy = request.headers().contains_key("x-amz-copy-source");
+                        paths.lock().unwrap().push(format!(
+                            "{} {}{}",
+                            request.method(),
+                            request.uri().path(),
+                            if copy { " (copy)" } else { "" }
+                        ));
+                        let response = match (&body, request.method()) {
+                            (_, &Method::PUT) => Response::new(Body::empty()),
+                            (Some(body), _) => Response::new(Body::from(body.clone())),
+                            (None, _) => {
+                                let mut response = Response::new(Body::empty());
+                                *response.status_mut() = StatusCode::NOT_FOUND;
+                                response
+                            }
+                        };
+                        async { Ok::<_, Infallible>(response) }
+                    }))
+                }
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let endpoint = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let storage = Storage::with_credentials(
+            Options {
+                kafka_region: Region::Custom {
+                    name: "local".to_string(),
+                    endpoint,
+                },
+                kafka_bucket: "bucket".to_string(),
+            },
+            "test",
+            "test",
+        );
+        (storage, paths)
+    }
+
+    #[tokio::test]
+    async fn test_upload_once() {
+        let (storage, paths) = stub_storage(None).await;
+        assert!(storage
+            .upload_once("key".to_string(), vec![1])
+            .await
+            .unwrap());
+        assert_eq!(*paths.lock().unwrap(), [
+            "HEAD /bucket/key",
+            "PUT /bucket/key"
+        ]);
+
+        // Existing objects are only copied onto themselves
+        let (storage, paths) = stub_storage(Some(vec![1])).await;
+        assert!(!storage
+            .upload_once("key".to_string(), vec![1])
+            .await
+            .unwrap());
+        assert_eq!(*paths.lock().unwrap(), [
+            "HEAD /bucket/key",
+            "PUT /bucket/key (copy)"
+        ]);
+    }
+
     #[ignore] // BEWARE: Writes to S3 and doesn't delete test objects
     #[tokio::test]
     #[traced_test]
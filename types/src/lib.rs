@@ -1,8 +1,14 @@
 mod from_proto;
 mod into_proto;
 mod kafka;
+pub mod logging;
+mod maybe_large;
 pub mod proto;
 
-pub use from_proto::FromProto;
+pub use from_proto::{FromProto, MissingField, TryFromProto};
 pub use into_proto::IntoProto;
-pub use kafka::{Kafka, KafkaConsumer, KafkaProducer, Options};
+pub use kafka::{
+    cleanup_large_messages, Kafka, KafkaConsumer, KafkaOffset, KafkaProducer, Options, ProtoFile,
+    ReplayStart,
+};
+pub use maybe_large::MaybeLarge;
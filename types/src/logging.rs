@@ -1,5 +1,3 @@
-#![warn(clippy::all, clippy::pedantic, clippy::cargo, clippy::nursery)]
-
 use core::str::FromStr;
 
 use anyhow::{anyhow, Context as _, Error as AnyError, Result as AnyResult};
@@ -43,13 +41,16 @@ pub struct LogOptions {
 }
 
 impl LogOptions {
-    #[allow(dead_code)]
-    pub fn init(&self) -> AnyResult<()> {
+    /// Install the global log collector and log the binary's version.
+    ///
+    /// `name` is the calling crate's `CARGO_CRATE_NAME`, which the verbosity
+    /// flags raise the log level of.
+    pub fn init(&self, name: &str, version: &str, commit: &str) -> AnyResult<()> {
         let log_filter = match self.verbose {
             0 => "info".to_owned(),
-            1 => format!("{}=debug,lib=debug", env!("CARGO_CRATE_NAME")),
-            2 => format!("{}=trace,lib=trace", env!("CARGO_CRATE_NAME")),
-            3 => format!("{}=trace,lib=trace,debug", env!("CARGO_CRATE_NAME")),
+            1 => format!("{}=debug,lib=debug", name),
+            2 => format!("{}=trace,lib=trace", name),
+            3 => format!("{}=trace,lib=trace,debug", name),
             _ => "trace".to_owned(),
         };
         let log_filter = if self.log_filter.is_empty() {
@@ -75,16 +76,16 @@ impl LogOptions {
         // Log version information
         info!(
             "{name} {version} {commit}",
-            name = env!("CARGO_CRATE_NAME"),
-            version = env!("CARGO_PKG_VERSION"),
-            commit = &env!("COMMIT_SHA")[..8],
+            name = name,
+            version = version,
+            commit = commit.get(..8).unwrap_or(commit),
         );
         Ok(())
     }
 }
 
 #[cfg(test)]
-pub mod test {
+mod tests {
     use pretty_assertions::assert_eq;
 
     use super::*;
@@ -1,47 +1,131 @@
 //! Abstraction type over large event messages.
 //!
 //! *Note*: The implementation is handwritten instead of generated from proto
-//! files so that it is generic.
-//!
-//! ## To do
-//!
-//! * Copy free implementation maintaining only a reference to the object on
-//!   encoding.
+//! files so that it is generic. It is wire compatible with
+//! `zeroex.maybe_large.MaybeLarge`, as embedded messages are encoded the same
+//! as bytes.
 
-use prost::{Message, Oneof};
+use prost::{
+    bytes::{Buf, BufMut},
+    encoding::{message, skip_field, DecodeContext, WireType},
+    DecodeError, Message,
+};
 
-const LARGE_TRESHOLD: usize = 1_000_000;
+use crate::proto::Large;
 
-// Message also implements Debug + Default
-#[derive(Clone, PartialEq, Message)]
-pub struct MaybeLarge<T>
-where
-    T: Message + Default + Send + Sync,
-{
-    #[prost(oneof = "MaybeLargeEnum", tags = "1, 2")]
-    pub maybe_large: Option<MaybeLargeEnum<T>>,
-}
+const LARGE_TAG: u32 = 1;
+const EMBEDDED_TAG: u32 = 2;
 
-#[derive(Clone, PartialEq, Oneof)]
-pub enum MaybeLargeEnum<T: Message + Default + Send + Sync>
-where
-    T: Message + Default + Send + Sync,
-{
-    #[prost(message, tag = "1")]
+#[derive(Clone, PartialEq, Debug)]
+pub enum MaybeLarge<T> {
+    /// Pointer to the encoded message in object storage
     Large(Large),
-    #[prost(message, tag = "2")]
-    Event(T),
+    Embedded(T),
+}
+
+impl<T: Message + Default> MaybeLarge<T> {
+    /// Encode `event` as an embedded message without taking ownership.
+    pub fn encode_embedded(event: &T) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(message::encoded_len(EMBEDDED_TAG, event));
+        message::encode(EMBEDDED_TAG, event, &mut buf);
+        buf
+    }
+}
+
+impl<T: Message + Default> Default for MaybeLarge<T> {
+    fn default() -> Self {
+        Self::Embedded(T::default())
+    }
 }
 
-#[derive(Clone, PartialEq, Message)]
-pub struct Large {
-    /// Path relative to the base URI containing a `Batch` object.
-    #[prost(string, tag = "1")]
-    payload_path: String,
+impl<T: Message + Default> Message for MaybeLarge<T> {
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        match self {
+            Self::Large(large) => message::encode(LARGE_TAG, large, buf),
+            Self::Embedded(event) => message::encode(EMBEDDED_TAG, event, buf),
+        }
+    }
+
+    fn merge_field<B: Buf>(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        match tag {
+            LARGE_TAG => {
+                let mut large = Large::default();
+                message::merge(wire_type, &mut large, buf, ctx)?;
+                *self = Self::Large(large);
+                Ok(())
+            }
+            EMBEDDED_TAG => {
+                if let Self::Embedded(event) = self {
+                    message::merge(wire_type, event, buf, ctx)
+                } else {
+                    let mut event = T::default();
+                    message::merge(wire_type, &mut event, buf, ctx)?;
+                    *self = Self::Embedded(event);
+                    Ok(())
+                }
+            }
+            _ => skip_field(wire_type, tag, buf, ctx),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        match self {
+            Self::Large(large) => message::encoded_len(LARGE_TAG, large),
+            Self::Embedded(event) => message::encoded_len(EMBEDDED_TAG, event),
+        }
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use prost::Message;
+    use crate::{proto, proto::large::Compression, IntoProto};
+
+    fn event() -> proto::H256 {
+        web3::types::H256::repeat_byte(0x42).into_proto()
+    }
+
+    #[test]
+    fn test_embedded_roundtrip() {
+        let encoded = MaybeLarge::encode_embedded(&event());
+        assert_eq!(
+            MaybeLarge::Embedded(event()).encode_to_vec(),
+            encoded
+        );
+        let decoded = MaybeLarge::<proto::H256>::decode(encoded.as_slice()).unwrap();
+        assert_eq!(decoded, MaybeLarge::Embedded(event()));
+    }
+
+    #[test]
+    fn test_large_roundtrip() {
+        let large = MaybeLarge::<proto::H256>::Large(Large {
+            payload_path: "2021/2021-08-31/object".to_string(),
+            compression:  Compression::Zstd.into(),
+        });
+        let decoded = MaybeLarge::<proto::H256>::decode(large.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, large);
+    }
+
+    #[test]
+    fn test_compatible_with_generated() {
+        let generated = proto::MaybeLarge {
+            maybe_large: Some(proto::maybe_large::MaybeLarge::Embedded(
+                event().encode_to_vec(),
+            )),
+        };
+        assert_eq!(
+            generated.encode_to_vec(),
+            MaybeLarge::encode_embedded(&event())
+        );
+    }
 }
@@ -12,4 +12,22 @@ pub mod zeroex;
 
 include!(concat!(env!("OUT_DIR"), "/zeroex.maybe_large.rs"));
 
-include!(concat!(env!("OUT_DIR"), "/zeroex.reorgable.rs"));
+impl crate::kafka::ProtoFile for BlockHeader {
+    const PROTO_FILE: &'static str = "web3/block_header.proto";
+}
+
+impl crate::kafka::ProtoFile for zeroex::reorgable::Event {
+    const PROTO_FILE: &'static str = "reorgable.proto";
+}
+
+impl crate::kafka::ProtoFile for BlockData {
+    const PROTO_FILE: &'static str = "web3/block_data.proto";
+}
+
+impl crate::kafka::ProtoFile for zeroex::OrderEvent {
+    const PROTO_FILE: &'static str = "zeroex/order_event.proto";
+}
+
+impl crate::kafka::ProtoFile for zeroex::OrderSnapshot {
+    const PROTO_FILE: &'static str = "zeroex/order_snapshot.proto";
+}
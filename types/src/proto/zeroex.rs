@@ -1 +1,5 @@
 include!(concat!(env!("OUT_DIR"), "/zeroex.rs"));
+
+pub mod reorgable {
+    include!(concat!(env!("OUT_DIR"), "/zeroex.reorgable.rs"));
+}